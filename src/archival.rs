@@ -0,0 +1,87 @@
+use std::{
+    fs::read_to_string,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{date::parse_rfc3339, files::is_hex_sha, revision::Metadata, Revision};
+
+/// Name of the file git substitutes commit information into when creating an
+/// archive of a repository with `git archive`.
+pub(crate) const FILE_NAME: &str = ".git_archival.txt";
+
+/// Find the archival file in the directory or its parents, as the root of an
+/// archive of a repository may be a parent of the crate.
+pub(crate) fn find(current_dir: &Path) -> io::Result<Option<(PathBuf, String)>> {
+    for dir in current_dir.ancestors() {
+        let path = dir.join(FILE_NAME);
+        match read_to_string(&path) {
+            Ok(contents) => return Ok(Some((path, contents))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Parse the revision and the metadata requested from the contents of the
+/// archival file, which has the format used by setuptools-scm:
+///
+/// ```text
+/// node: $Format:%H$
+/// node-date: $Format:%cI$
+/// describe-name: $Format:%(describe:tags=true)$
+/// ref-names: $Format:%D$
+/// ```
+///
+/// Only `node` is required. Returns `None` if the file was not substituted by
+/// git, which is the case when it is read from the repository itself or from
+/// an archive of a repository not configured to substitute it.
+pub(crate) fn parse(contents: &str, metadata: &Metadata) -> Option<Revision> {
+    if contents.contains("$Format:") {
+        return None;
+    }
+    let field = |name: &str| {
+        contents.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name)
+                .then(|| value.trim())
+                .filter(|v| !v.is_empty())
+        })
+    };
+
+    let sha = field("node").filter(|sha| is_hex_sha(sha))?;
+    let mut revision = Revision::new(sha.to_string(), false);
+
+    // The ref names are formatted like `HEAD -> main, tag: v1.0`, with
+    // parentheses around them if `%d` is used instead of `%D`.
+    let ref_names = field("ref-names")
+        .map(|names| names.trim_start_matches('(').trim_end_matches(')'))
+        .unwrap_or_default()
+        .split(", ")
+        .map(str::trim);
+    if metadata.branch {
+        revision.branch = ref_names
+            .clone()
+            .find_map(|name| name.strip_prefix("HEAD -> "))
+            .map(str::to_string);
+    }
+    if metadata.tag {
+        // Match `git tag --points-at`, which lists tags in order of name.
+        revision.tag = ref_names
+            .filter_map(|name| name.strip_prefix("tag: "))
+            .min()
+            .map(str::to_string);
+    }
+    if metadata.describe {
+        revision.describe = field("describe-name").map(str::to_string);
+    }
+    if metadata.commit_timestamp {
+        if let Some(date) = field("node-date") {
+            revision.commit_timestamp = parse_rfc3339(date);
+            revision.commit_date = Some(date.to_string());
+        }
+    }
+
+    Some(revision)
+}
@@ -0,0 +1,74 @@
+use crate::{GitRevision, ParseGitRevisionError};
+
+/// The git revision and metadata embedded in a crate, created with
+/// [`build_info!`][crate::build_info].
+///
+/// Metadata is `None` if it was not enabled in the [`Builder`][crate::Builder]
+/// in the build script, or was not available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct BuildInfo {
+    /// The git revision, as emitted in `GIT_REVISION`.
+    pub revision: &'static str,
+    /// The value of `GIT_BRANCH`.
+    pub branch: Option<&'static str>,
+    /// The value of `GIT_TAG`.
+    pub tag: Option<&'static str>,
+    /// The value of `GIT_DESCRIBE`.
+    pub describe: Option<&'static str>,
+    /// The value of `GIT_COMMIT_TIMESTAMP`, in seconds since the unix epoch.
+    pub commit_timestamp: Option<&'static str>,
+    /// The value of `GIT_COMMIT_TIMESTAMP_RFC3339`.
+    pub commit_date: Option<&'static str>,
+    /// The value of `GIT_COMMIT_AUTHOR_DATE`.
+    pub author_date: Option<&'static str>,
+    /// The value of `GIT_PATH_IN_VCS`.
+    pub path_in_vcs: Option<&'static str>,
+    /// The value of `GIT_CRATE_REVISION`.
+    pub crate_revision: Option<&'static str>,
+    /// The value of `GIT_CRATE_TREE`.
+    pub crate_tree: Option<&'static str>,
+    /// The value of `GIT_REVISION_SOURCE`.
+    pub revision_source: Option<&'static str>,
+    /// The value of `GIT_OBJECT_FORMAT`.
+    pub object_format: Option<&'static str>,
+}
+
+impl BuildInfo {
+    #[doc(hidden)]
+    #[allow(clippy::too_many_arguments)]
+    pub const fn __new(
+        revision: &'static str,
+        branch: Option<&'static str>,
+        tag: Option<&'static str>,
+        describe: Option<&'static str>,
+        commit_timestamp: Option<&'static str>,
+        commit_date: Option<&'static str>,
+        author_date: Option<&'static str>,
+        path_in_vcs: Option<&'static str>,
+        crate_revision: Option<&'static str>,
+        crate_tree: Option<&'static str>,
+        revision_source: Option<&'static str>,
+        object_format: Option<&'static str>,
+    ) -> Self {
+        Self {
+            revision,
+            branch,
+            tag,
+            describe,
+            commit_timestamp,
+            commit_date,
+            author_date,
+            path_in_vcs,
+            crate_revision,
+            crate_tree,
+            revision_source,
+            object_format,
+        }
+    }
+
+    /// Parse the revision into a [`GitRevision`].
+    pub const fn git_revision(&self) -> Result<GitRevision<'static>, ParseGitRevisionError> {
+        GitRevision::parse(self.revision)
+    }
+}
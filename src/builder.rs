@@ -0,0 +1,820 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::{
+    directive::{self, Directives, Format},
+    revision::Metadata,
+    source,
+    sources::{self, RevisionSource, SourceContext},
+    Error, GitRevision, Revision, SourceKind,
+};
+
+/// How the git repository containing the crate is read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Run the `git` executable, and read the repository files directly if the
+    /// `git` executable cannot be found.
+    #[default]
+    Auto,
+    /// Run the `git` executable.
+    Command,
+    /// Read the refs in the git directory directly, without the `git`
+    /// executable. Modifications in the working directory are not detected,
+    /// and the revision is never dirty.
+    Files,
+    /// Read the repository with [gitoxide](https://crates.io/crates/gix), a
+    /// pure Rust implementation of git. Requires the `gix` feature.
+    ///
+    /// Repositories using the SHA-256 object format are not supported, and
+    /// fail with [`Error::UnsupportedObjectFormat`].
+    #[cfg(feature = "gix")]
+    Gix,
+    /// Read the repository with [libgit2](https://crates.io/crates/git2).
+    /// Requires the `git2` feature.
+    ///
+    /// Repositories using the SHA-256 object format are not supported, and
+    /// fail with [`Error::UnsupportedObjectFormat`].
+    #[cfg(feature = "git2")]
+    Git2,
+}
+
+/// What [`Builder::emit`] does when the revision cannot be found, such as when
+/// building from a source tarball that has neither a git repository nor a
+/// `.cargo_vcs_info.json` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fallback {
+    /// Emit the value as the revision, and the error as a cargo warning, so
+    /// that crates using the revision still compile. Metadata is not emitted,
+    /// the generated files contain the value as the revision, and the value
+    /// cannot be parsed by [`GitRevision`][crate::GitRevision] unless it is a
+    /// commit hash.
+    Placeholder(String),
+    /// Emit only the error as a cargo warning, with the directives to rerun
+    /// the build script, which fails to compile crates that use the revision with [`git_revision!`][crate::git_revision] or
+    /// `env!`.
+    Nothing,
+    /// Fail the build script with the error.
+    Error,
+}
+
+impl Default for Fallback {
+    /// Defaults to the placeholder `unknown`.
+    fn default() -> Self {
+        Fallback::Placeholder("unknown".to_string())
+    }
+}
+
+/// When a dirty revision fails the build, to prevent shipping binaries built
+/// from changes that are not committed.
+///
+/// The `CRATE_GIT_REVISION_REQUIRE_CLEAN` environment variable overrides the
+/// policy, requiring a clean working directory when set to `1`, and allowing
+/// a dirty one when set to `0`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequireClean {
+    /// Dirty revisions are allowed.
+    #[default]
+    Never,
+    /// Dirty revisions fail release builds, where cargo sets `PROFILE` to
+    /// `release`.
+    Release,
+    /// Dirty revisions fail all builds.
+    Always,
+}
+
+/// Builder for configuring how the git revision is discovered and emitted.
+///
+/// [`init`][crate::init] is a shortcut for `Builder::new().emit()`. Use the
+/// builder when the defaults are not suitable for a crate.
+///
+/// ### Examples
+///
+/// ```no_run
+/// crate_git_revision::Builder::new()
+///     .env_name("MY_GIT_REVISION")
+///     .dirty_suffix("-modified")
+///     .emit();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Builder {
+    manifest_dir: Option<PathBuf>,
+    env_name: String,
+    dirty_suffix: String,
+    pub(crate) backend: Backend,
+    pub(crate) metadata: Metadata,
+    emit_short: bool,
+    emit_dirty: bool,
+    out_dir: Option<PathBuf>,
+    generate_source: bool,
+    pub(crate) revision_file: bool,
+    pub(crate) watch_tracked_files: bool,
+    pub(crate) dirty_crate_only: bool,
+    pub(crate) dirty_paths: Vec<PathBuf>,
+    ci_revision: bool,
+    #[cfg(feature = "content-hash")]
+    content_hash_fallback: bool,
+    sources: Option<Vec<Arc<dyn RevisionSource>>>,
+    fallback: Fallback,
+    pub(crate) require_clean: RequireClean,
+    pub(crate) detect_vendored: bool,
+    pub(crate) detect_untracked: bool,
+    emit_revision_source: bool,
+    emit_object_format: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    /// Create a builder with the default configuration.
+    pub fn new() -> Self {
+        Self {
+            manifest_dir: None,
+            env_name: "GIT_REVISION".to_string(),
+            dirty_suffix: "-dirty".to_string(),
+            backend: Backend::Auto,
+            metadata: Metadata::default(),
+            emit_short: false,
+            emit_dirty: false,
+            out_dir: None,
+            generate_source: false,
+            revision_file: false,
+            watch_tracked_files: false,
+            dirty_crate_only: false,
+            dirty_paths: Vec::new(),
+            ci_revision: false,
+            #[cfg(feature = "content-hash")]
+            content_hash_fallback: false,
+            sources: None,
+            fallback: Fallback::default(),
+            require_clean: RequireClean::Never,
+            detect_vendored: false,
+            detect_untracked: false,
+            emit_revision_source: false,
+            emit_object_format: false,
+        }
+    }
+
+    /// Directory of the crate to get the git revision of.
+    ///
+    /// Defaults to the current directory, which is the crate's manifest
+    /// directory when called from a build script.
+    pub fn manifest_dir(mut self, manifest_dir: impl Into<PathBuf>) -> Self {
+        self.manifest_dir = Some(manifest_dir.into());
+        self
+    }
+
+    /// Name of the environment variable the git revision is injected into.
+    ///
+    /// Defaults to `GIT_REVISION`.
+    pub fn env_name(mut self, env_name: impl Into<String>) -> Self {
+        self.env_name = env_name.into();
+        self
+    }
+
+    /// Suffix appended to the git revision when the working directory is
+    /// dirty.
+    ///
+    /// Defaults to `-dirty`.
+    pub fn dirty_suffix(mut self, dirty_suffix: impl Into<String>) -> Self {
+        self.dirty_suffix = dirty_suffix.into();
+        self
+    }
+
+    /// How the git repository containing the crate is read.
+    ///
+    /// Defaults to [`Backend::Auto`], which runs the `git` executable when
+    /// available, and otherwise reads the repository files directly.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Rerun the build script when any file tracked by git under the crate's
+    /// directory changes, so that the revision is marked dirty as soon as a
+    /// file is edited, and no longer dirty when the edit is reverted, without
+    /// the file having to be staged.
+    ///
+    /// Defaults to `false`, in which case edits to files are only noticed when
+    /// the index changes, such as when files are staged, or when something
+    /// else causes the build script to rerun. Has no effect with
+    /// [`Backend::Files`], which does not detect dirty working directories.
+    pub fn watch_tracked_files(mut self, watch: bool) -> Self {
+        self.watch_tracked_files = watch;
+        self
+    }
+
+    /// Only consider changes to files under the crate's directory, and the
+    /// paths added with [`Builder::dirty_path`], when determining whether the
+    /// working directory is dirty.
+    ///
+    /// Defaults to `false`, in which case changes to any file tracked in the
+    /// repository make the revision dirty, which in a monorepo includes
+    /// changes to unrelated crates. Untracked files are never considered.
+    ///
+    /// ### Examples
+    ///
+    /// For a crate in a workspace, also consider changes to the workspace's
+    /// lock file:
+    ///
+    /// ```no_run
+    /// crate_git_revision::Builder::new()
+    ///     .dirty_crate_only(true)
+    ///     .dirty_path("../Cargo.lock")
+    ///     .emit();
+    /// ```
+    pub fn dirty_crate_only(mut self, crate_only: bool) -> Self {
+        self.dirty_crate_only = crate_only;
+        self
+    }
+
+    /// Add a path, relative to the crate's directory, that changes are
+    /// considered in when [`Builder::dirty_crate_only`] is enabled. The path
+    /// may be a file or a directory, and must be within the repository.
+    pub fn dirty_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dirty_paths.push(path.into());
+        self
+    }
+
+    /// When the crate is neither in a git repository nor a published crate,
+    /// use the commit hash from the environment variables CI services set,
+    /// `GITHUB_SHA`, `CI_COMMIT_SHA`, `BUILDKITE_COMMIT`, `CIRCLE_SHA1`,
+    /// `TRAVIS_COMMIT` or `BITBUCKET_COMMIT`, such as when building in a
+    /// container without the `.git` directory.
+    ///
+    /// Defaults to `false`. The variables are ignored for crates in a
+    /// repository, as they are the commit of the repository being built by CI,
+    /// which for dependencies is not the commit of the dependency.
+    pub fn ci_revision(mut self, enable: bool) -> Self {
+        self.ci_revision = enable;
+        self
+    }
+
+    /// When the crate is neither in a git repository nor a published crate,
+    /// such as when built from a source tarball, use a hash of the crate's
+    /// files as the revision, `unknown+src.<hash>`, instead of failing.
+    ///
+    /// The files hashed are those `cargo package` would include, respecting the
+    /// `include` and `exclude` fields of the manifest, and the build script
+    /// reruns when any of them change. The revision is never dirty, and cannot
+    /// be parsed by [`GitRevision`][crate::GitRevision].
+    ///
+    /// Defaults to `false`. Requires the `content-hash` feature.
+    #[cfg(feature = "content-hash")]
+    pub fn content_hash_fallback(mut self, fallback: bool) -> Self {
+        self.content_hash_fallback = fallback;
+        self
+    }
+
+    /// Replace the chain of sources the revision is read from, which are tried
+    /// in order until one has a revision. See [`sources`] for the default
+    /// chain and the sources available.
+    ///
+    /// [`Builder::ci_revision`] and `Builder::content_hash_fallback` only add
+    /// their sources to the default chain, and have no effect when the chain
+    /// is replaced.
+    pub fn sources(mut self, sources: Vec<Box<dyn RevisionSource>>) -> Self {
+        self.sources = Some(sources.into_iter().map(Arc::from).collect());
+        self
+    }
+
+    /// What [`Builder::emit`] does when the revision cannot be found.
+    ///
+    /// Defaults to [`Fallback::Placeholder`] with `unknown`. Has no effect on
+    /// [`Builder::try_emit`], which returns the error.
+    ///
+    /// ### Examples
+    ///
+    /// Fail the build instead of embedding a placeholder:
+    ///
+    /// ```rust
+    /// use crate_git_revision::{Builder, Fallback};
+    ///
+    /// Builder::new().fallback(Fallback::Error);
+    /// ```
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Fail the build when the working directory of the repository containing
+    /// the crate is dirty, listing the modified files in the error. The error
+    /// is not handled by [`Builder::fallback`].
+    ///
+    /// Only revisions read from the repository are checked, and the build fails
+    /// with [`Error::CleanUnverified`] when they are read without the `git`
+    /// executable, which does not detect changes. Published crates
+    /// that were published with `--allow-dirty` are still built, as their
+    /// changes are not in the working directory being built.
+    ///
+    /// Defaults to [`RequireClean::Never`].
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use crate_git_revision::{Builder, RequireClean};
+    ///
+    /// Builder::new().require_clean(RequireClean::Release);
+    /// ```
+    pub fn require_clean(mut self, require_clean: RequireClean) -> Self {
+        self.require_clean = require_clean;
+        self
+    }
+
+    /// Ignore the git repository containing the crate, with a cargo warning,
+    /// when the crate appears to have been vendored or copied into it rather
+    /// than developed in it, as the revision of that repository is not the
+    /// revision of the crate. Other sources, and the [`Builder::fallback`], are
+    /// used instead.
+    ///
+    /// A crate is considered vendored when the `repository` field of its
+    /// manifest does not contain the name of the repository of any of the
+    /// remotes. Repositories without remotes, and crates without a
+    /// `repository` field, are not checked. Cargo's clones of git
+    /// dependencies, whose remote is `$CARGO_HOME/git/db/<name>-<hash>`, match
+    /// by the name. See also [`Builder::detect_untracked`].
+    ///
+    /// Defaults to `false`, as the remotes of forks and mirrors may be named
+    /// differently to the `repository` field. Has no effect with
+    /// [`Backend::Files`], which does not read the config.
+    pub fn detect_vendored(mut self, detect: bool) -> Self {
+        self.detect_vendored = detect;
+        self
+    }
+
+    /// Also consider the crate vendored, and ignore the git repository
+    /// containing it, when its `Cargo.toml` is not tracked in the repository.
+    ///
+    /// Defaults to `false`, as crates created in a repository, such as with
+    /// `cargo new`, are not tracked until they are added. Has no effect with
+    /// [`Backend::Files`], which does not read the index.
+    pub fn detect_untracked(mut self, detect: bool) -> Self {
+        self.detect_untracked = detect;
+        self
+    }
+
+    /// Emit `GIT_BRANCH` containing the name of the branch checked out.
+    ///
+    /// Not emitted when HEAD is detached, or for published crates.
+    pub fn emit_branch(mut self, emit: bool) -> Self {
+        self.metadata.branch = emit;
+        self
+    }
+
+    /// Emit `GIT_TAG` containing the name of a tag pointing at the commit,
+    /// choosing the first in order of name if there are multiple.
+    ///
+    /// Not emitted when no tag points at the commit, or for published crates.
+    pub fn emit_tag(mut self, emit: bool) -> Self {
+        self.metadata.tag = emit;
+        self
+    }
+
+    /// Emit `GIT_DESCRIBE` containing the output of `git describe --tags
+    /// --always`, with the dirty suffix if the working directory is dirty.
+    ///
+    /// Not emitted for published crates.
+    pub fn emit_describe(mut self, emit: bool) -> Self {
+        self.metadata.describe = emit;
+        self
+    }
+
+    /// Emit `GIT_COMMIT_TIMESTAMP` containing the committer date of the
+    /// commit in seconds since the unix epoch, and
+    /// `GIT_COMMIT_TIMESTAMP_RFC3339` containing the same date in RFC 3339
+    /// format.
+    ///
+    /// Not emitted for published crates.
+    pub fn emit_commit_timestamp(mut self, emit: bool) -> Self {
+        self.metadata.commit_timestamp = emit;
+        self
+    }
+
+    /// Emit `GIT_COMMIT_AUTHOR_DATE` containing the author date of the commit
+    /// in RFC 3339 format.
+    ///
+    /// Not emitted for published crates.
+    pub fn emit_author_date(mut self, emit: bool) -> Self {
+        self.metadata.author_date = emit;
+        self
+    }
+
+    /// Emit `GIT_CRATE_REVISION` containing the hash of the last commit that
+    /// changed a file under the crate's directory, which stays the same across
+    /// commits that only change other parts of the repository.
+    ///
+    /// Not emitted for published crates, with [`Backend::Files`], or when no
+    /// commit contains the crate's directory.
+    pub fn emit_crate_revision(mut self, emit: bool) -> Self {
+        self.metadata.crate_revision = emit;
+        self
+    }
+
+    /// Emit `GIT_CRATE_TREE` containing the hash of the git tree of the
+    /// crate's directory in the commit, `HEAD:<path>`, which identifies the
+    /// committed contents of the crate regardless of the history that led to
+    /// them.
+    ///
+    /// Not emitted for published crates, with [`Backend::Files`], or when the
+    /// commit does not contain the crate's directory.
+    pub fn emit_crate_tree(mut self, emit: bool) -> Self {
+        self.metadata.crate_tree = emit;
+        self
+    }
+
+    /// Emit `GIT_REVISION_SOURCE` containing where the crate is being built
+    /// from, to interpret the revision with: `registry` for published
+    /// packages, `git` for git dependencies and `cargo install --git`, `path`
+    /// for path dependencies of other projects, or `workspace` for crates in
+    /// the workspace being built. See [`SourceKind`].
+    ///
+    /// Path dependencies and workspaces are told apart by whether the target
+    /// directory is in the crate's workspace, so crates built with a target
+    /// directory elsewhere, such as with `CARGO_TARGET_DIR`, are reported as
+    /// path dependencies.
+    pub fn emit_revision_source(mut self, emit: bool) -> Self {
+        self.emit_revision_source = emit;
+        self
+    }
+
+    /// Emit `GIT_OBJECT_FORMAT` containing the object format of the repository,
+    /// `sha1` or `sha256`, which determines whether the commit hash has 40 or
+    /// 64 characters. See [`ObjectFormat`][crate::ObjectFormat].
+    ///
+    /// For revisions not read from a repository, the format is inferred from
+    /// the length of the hash, and nothing is emitted if the revision is not a
+    /// commit hash.
+    pub fn emit_object_format(mut self, emit: bool) -> Self {
+        self.emit_object_format = emit;
+        self
+    }
+
+    /// Emit `GIT_REVISION_SHORT` containing the first
+    /// [`Revision::SHORT_LEN`] characters of the commit hash.
+    pub fn emit_short(mut self, emit: bool) -> Self {
+        self.emit_short = emit;
+        self
+    }
+
+    /// Emit `GIT_DIRTY` containing `true` if the working directory is dirty,
+    /// otherwise `false`.
+    pub fn emit_dirty(mut self, emit: bool) -> Self {
+        self.emit_dirty = emit;
+        self
+    }
+
+    /// Directory files are generated in.
+    ///
+    /// Defaults to the `OUT_DIR` environment variable cargo sets for build
+    /// scripts.
+    pub fn out_dir(mut self, out_dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// Generate `git_revision.rs` in `OUT_DIR` containing typed constants for
+    /// the revision and the metadata enabled, for use in `const` contexts
+    /// without parsing environment variables.
+    ///
+    /// ### Examples
+    ///
+    /// In `build.rs`:
+    ///
+    /// ```ignore
+    /// crate_git_revision::Builder::new()
+    ///     .generate_source(true)
+    ///     .emit();
+    /// ```
+    ///
+    /// In the crate:
+    ///
+    /// ```ignore
+    /// mod git {
+    ///     include!(concat!(env!("OUT_DIR"), "/git_revision.rs"));
+    /// }
+    ///
+    /// const DIRTY: bool = git::DIRTY;
+    /// ```
+    ///
+    /// The file contains `REVISION`, `SHA`, `SHORT` and `DIRTY`, and `BRANCH`,
+    /// `TAG`, `DESCRIBE`, `COMMIT_TIMESTAMP`, `COMMIT_DATE`, `AUTHOR_DATE`,
+    /// `PATH_IN_VCS`, `CRATE_REVISION`, `CRATE_TREE`, `REVISION_SOURCE` and
+    /// `OBJECT_FORMAT`, which are `None` if not enabled or not available.
+    pub fn generate_source(mut self, generate: bool) -> Self {
+        self.generate_source = generate;
+        self
+    }
+
+    /// Write the git revision to `git_revision.txt` in `OUT_DIR`, for crates to
+    /// read with `include_str!`.
+    ///
+    /// Cargo recompiles a crate every time its build script reruns, and by
+    /// default the build script reruns whenever the index changes, such as
+    /// when any file in the repository is staged. With the revision file, the
+    /// index is not watched, and the files tracked under the crate's directory
+    /// are watched instead, as with [`Builder::watch_tracked_files`]. The build
+    /// script then reruns when a commit is made or checked out, or a file of
+    /// the crate is edited, but not when files are staged.
+    ///
+    /// Changes outside the crate's directory are only noticed when the build
+    /// script next reruns, so combine this with [`Builder::dirty_crate_only`]
+    /// for the dirty suffix to stay accurate. The file, and the file generated
+    /// by [`Builder::generate_source`], are only written when their contents
+    /// change.
+    ///
+    /// ### Examples
+    ///
+    /// In `build.rs`:
+    ///
+    /// ```ignore
+    /// crate_git_revision::Builder::new()
+    ///     .revision_file(true)
+    ///     .emit();
+    /// ```
+    ///
+    /// In the crate:
+    ///
+    /// ```ignore
+    /// pub const GIT_REVISION: &str = include_str!(concat!(env!("OUT_DIR"), "/git_revision.txt"));
+    /// ```
+    pub fn revision_file(mut self, write: bool) -> Self {
+        self.revision_file = write;
+        self
+    }
+
+    /// Emit the git revision environment variable to cargo.
+    ///
+    /// Intended to be called from within a build script, `build.rs` file, for
+    /// the crate. Errors are handled as configured with [`Builder::fallback`],
+    /// by default reporting them as cargo warnings and emitting a placeholder.
+    /// Use [`Builder::try_emit`] to handle errors.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the revision cannot be found and the fallback is
+    /// [`Fallback::Error`], if the revision is dirty and a clean build is
+    /// required with [`Builder::require_clean`], or if writing to stdout
+    /// fails.
+    pub fn emit(&self) {
+        if let Err(e) = self.emit_to_with_env(&mut std::io::stdout(), &process_env) {
+            panic!("Error getting git revision: {e}");
+        }
+    }
+
+    /// Emit the git revision environment variable to cargo, returning the
+    /// revision that was emitted.
+    ///
+    /// Nothing is emitted for the revision if an error is returned, and the
+    /// build script decides whether to panic, fall back, or continue.
+    pub fn try_emit(&self) -> Result<Revision, Error> {
+        self.try_emit_to_with_env(&mut std::io::stdout(), &process_env)
+    }
+
+    /// Emit to the writer, reading environment variables with the function,
+    /// and handle errors with the fallback.
+    pub(crate) fn emit_to_with_env(
+        &self,
+        w: &mut impl Write,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(), Error> {
+        let (e, mut directives) = match self.emit_revision(w, env) {
+            Ok(_) => return Ok(()),
+            // A placeholder would hide the changes a clean build is required
+            // to prevent.
+            Err((e @ (Error::Dirty { .. } | Error::CleanUnverified), _)) => return Err(e),
+            Err(error) => error,
+        };
+        // The reruns recorded by the sources are emitted, so that the build
+        // script reruns when a source may have a revision, such as when
+        // `CRATE_GIT_REVISION` is set.
+        match &self.fallback {
+            Fallback::Placeholder(placeholder) => {
+                directives.rustc_env(&self.env_name, placeholder, Format::Text)?;
+                self.write_files(&Revision::new(placeholder, false))?;
+            }
+            Fallback::Nothing => {}
+            Fallback::Error => return Err(e),
+        }
+        directive::warning(w, &format!("Error getting git revision: {e}"))?;
+        directives.write_to(w)?;
+        Ok(())
+    }
+
+    /// Emit to the writer, reading environment variables with the function, so
+    /// that tests do not depend on the environment of the process.
+    pub(crate) fn try_emit_to_with_env(
+        &self,
+        w: &mut impl Write,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Revision, Error> {
+        self.emit_revision(w, env).map_err(|(e, _)| e)
+    }
+
+    /// Emit to the writer, returning with an error the rerun directives
+    /// recorded by the sources, for the fallback to emit.
+    fn emit_revision(
+        &self,
+        w: &mut impl Write,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Revision, (Error, Directives)> {
+        let current_dir = match &self.manifest_dir {
+            Some(manifest_dir) => manifest_dir.clone(),
+            None => std::env::current_dir().map_err(|e| (e.into(), Directives::default()))?,
+        };
+
+        let mut context = SourceContext::new(self, &current_dir, env);
+        let mut revision = Ok(None);
+        for source in self.source_chain() {
+            revision = source.revision(&mut context);
+            if !matches!(revision, Ok(None)) {
+                break;
+            }
+        }
+        let reruns = rerun_directives(&context);
+        self.emit_found(w, env, &context, revision, reruns.clone())
+            .map_err(|e| (e, reruns))
+    }
+
+    /// Emit the revision found by the chain of sources, with the rerun
+    /// directives.
+    fn emit_found(
+        &self,
+        w: &mut impl Write,
+        env: &dyn Fn(&str) -> Option<String>,
+        context: &SourceContext<'_>,
+        revision: Result<Option<Revision>, Error>,
+        mut directives: Directives,
+    ) -> Result<Revision, Error> {
+        let current_dir = context.manifest_dir();
+        for warning in &context.warnings {
+            directive::warning(w, warning)?;
+        }
+        let mut revision = revision?.ok_or(Error::NotARepository)?;
+        if revision.object_format.is_none() {
+            revision.object_format = GitRevision::parse(&revision.sha)
+                .ok()
+                .map(|sha| sha.object_format());
+        }
+        if self.emit_revision_source {
+            // Cargo sets `OUT_DIR` for build scripts, so it is not watched.
+            let out_dir = self
+                .out_dir
+                .clone()
+                .or_else(|| env("OUT_DIR").map(PathBuf::from));
+            revision.source_kind = Some(SourceKind::classify(current_dir, out_dir.as_deref()));
+        }
+
+        // Values from the repository, files and environment variables are
+        // checked, so that they cannot end a directive early and inject
+        // others.
+        directive::check(
+            &format!("rustc-env={}", self.env_name),
+            &revision.sha,
+            Format::Revision,
+        )?;
+        directives.rustc_env(
+            &self.env_name,
+            &revision.to_string_with_suffix(&self.dirty_suffix),
+            Format::Text,
+        )?;
+        if self.emit_short {
+            directives.rustc_env("GIT_REVISION_SHORT", revision.short(), Format::Revision)?;
+        }
+        if self.emit_dirty {
+            directives.rustc_env("GIT_DIRTY", &revision.dirty.to_string(), Format::Text)?;
+        }
+        if let Some(path_in_vcs) = &revision.path_in_vcs {
+            directives.rustc_env("GIT_PATH_IN_VCS", path_in_vcs, Format::Text)?;
+        }
+        if let Some(branch) = &revision.branch {
+            directives.rustc_env("GIT_BRANCH", branch, Format::RefName)?;
+        }
+        if let Some(tag) = &revision.tag {
+            directives.rustc_env("GIT_TAG", tag, Format::RefName)?;
+        }
+        if let Some(describe) = &revision.describe {
+            // The dirty suffix is not a ref name, and is checked as text.
+            directive::check("rustc-env=GIT_DESCRIBE", describe, Format::RefName)?;
+            let describe = revision.describe_with_suffix(&self.dirty_suffix);
+            directives.rustc_env("GIT_DESCRIBE", &describe.unwrap_or_default(), Format::Text)?;
+        }
+        if let Some(commit_timestamp) = revision.commit_timestamp {
+            directives.rustc_env(
+                "GIT_COMMIT_TIMESTAMP",
+                &commit_timestamp.to_string(),
+                Format::Text,
+            )?;
+        }
+        if let Some(commit_date) = &revision.commit_date {
+            directives.rustc_env("GIT_COMMIT_TIMESTAMP_RFC3339", commit_date, Format::Date)?;
+        }
+        if let Some(author_date) = &revision.author_date {
+            directives.rustc_env("GIT_COMMIT_AUTHOR_DATE", author_date, Format::Date)?;
+        }
+        if let Some(crate_revision) = &revision.crate_revision {
+            directives.rustc_env("GIT_CRATE_REVISION", crate_revision, Format::Sha)?;
+        }
+        if let Some(crate_tree) = &revision.crate_tree {
+            directives.rustc_env("GIT_CRATE_TREE", crate_tree, Format::Sha)?;
+        }
+        if let Some(object_format) = revision.object_format.filter(|_| self.emit_object_format) {
+            directives.rustc_env("GIT_OBJECT_FORMAT", object_format.as_str(), Format::Text)?;
+        }
+        if let Some(source_kind) = revision.source_kind {
+            directives.rustc_env("GIT_REVISION_SOURCE", source_kind.as_str(), Format::Text)?;
+        }
+
+        // The files are written first, so that if writing them fails nothing
+        // is emitted, and the fallback is not emitted after the revision.
+        self.write_files(&revision)?;
+        directives.write_to(w)?;
+
+        Ok(revision)
+    }
+
+    /// Write the generated files that are enabled for the revision.
+    fn write_files(&self, revision: &Revision) -> io::Result<()> {
+        if self.generate_source {
+            source::write(&self.resolve_out_dir()?, revision, &self.dirty_suffix)?;
+        }
+        if self.revision_file {
+            source::write_revision(&self.resolve_out_dir()?, revision, &self.dirty_suffix)?;
+        }
+        Ok(())
+    }
+
+    /// The chain of sources configured, or the default chain.
+    fn source_chain(&self) -> Vec<&dyn RevisionSource> {
+        if let Some(sources) = &self.sources {
+            return sources.iter().map(|source| &**source).collect();
+        }
+        let mut chain: Vec<&dyn RevisionSource> = vec![
+            &sources::VcsInfo,
+            &sources::Git,
+            &sources::EnvOverride,
+            &sources::GitArchival,
+        ];
+        if self.ci_revision {
+            chain.push(&sources::CiEnv);
+        }
+        #[cfg(feature = "content-hash")]
+        if self.content_hash_fallback {
+            chain.push(&sources::ContentHash);
+        }
+        chain
+    }
+
+    fn resolve_out_dir(&self) -> io::Result<PathBuf> {
+        match &self.out_dir {
+            Some(out_dir) => Ok(out_dir.clone()),
+            None => std::env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "OUT_DIR environment variable not set",
+                    )
+                }),
+        }
+    }
+}
+
+/// The rerun directives recorded by the sources. Published crates have none,
+/// and rely on cargo's default of rerunning when any file in the crate
+/// changes, which any directive disables. If a path cannot be written, such as
+/// one that is not valid unicode, none are emitted, so that the default
+/// applies rather than the path not being watched.
+fn rerun_directives(context: &SourceContext<'_>) -> Directives {
+    let mut directives = Directives::default();
+    let reruns = context
+        .rerun_if_changed
+        .iter()
+        .try_for_each(|file| directives.rerun_if_changed(file))
+        .and_then(|()| {
+            context
+                .rerun_if_env_changed
+                .iter()
+                .try_for_each(|name| directives.rerun_if_env_changed(name))
+        });
+    if let Err(e) = reruns {
+        directives = Directives::default();
+        directives.warning(&format!(
+            "Rerunning the build script when any file in the crate changes, {e}"
+        ));
+    }
+    directives
+}
+
+/// Read an environment variable of the build script's process.
+fn process_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
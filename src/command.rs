@@ -0,0 +1,262 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{revision::Metadata, Error, ObjectFormat, Revision};
+
+/// A git repository read by running the `git` executable.
+#[derive(Clone, Debug)]
+pub(crate) struct Repository {
+    current_dir: PathBuf,
+    /// The git directory as reported by git, which is relative to the current
+    /// directory when it is the root of the repository.
+    pub(crate) git_dir: PathBuf,
+    /// The git directory shared by all working trees, containing the refs.
+    pub(crate) common_dir: PathBuf,
+    pub(crate) object_format: ObjectFormat,
+}
+
+impl Repository {
+    /// Find the repository containing the directory.
+    pub(crate) fn discover(current_dir: &Path) -> Result<Self, Error> {
+        let output = git_output(
+            current_dir,
+            &[
+                "rev-parse",
+                "--git-dir",
+                "--git-common-dir",
+                "--show-object-format",
+            ],
+        )?;
+        let mut lines = output
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty());
+        let git_dir = lines.next().map(path_from_bytes).unwrap_or_default();
+        let common_dir = lines
+            .next()
+            .map(path_from_bytes)
+            .unwrap_or_else(|| git_dir.clone());
+        // Versions of git before 2.25 echo the option back, and only support
+        // SHA-1.
+        let object_format = lines
+            .next()
+            .and_then(|line| ObjectFormat::from_config(Some(&String::from_utf8_lossy(line))))
+            .unwrap_or(ObjectFormat::Sha1);
+        // In a subdirectory git reports an absolute git dir, but a common dir
+        // relative to the subdirectory. Make them consistent.
+        let common_dir = if git_dir.is_absolute() && common_dir.is_relative() {
+            fs::canonicalize(current_dir.join(common_dir))?
+        } else {
+            common_dir
+        };
+        Ok(Self {
+            current_dir: current_dir.to_path_buf(),
+            git_dir,
+            common_dir,
+            object_format,
+        })
+    }
+
+    /// Get the revision of the commit checked out, and the metadata requested.
+    pub(crate) fn revision(&self, metadata: &Metadata) -> Result<Revision, Error> {
+        let mut describe_args = vec![
+            "describe",
+            "--always",
+            "--exclude=*",
+            "--long",
+            "--abbrev=1000",
+        ];
+        if metadata.dirty_paths.is_none() {
+            describe_args.push("--dirty");
+        }
+        let git_describe = self.git(&describe_args)?;
+        let mut revision = match git_describe.strip_suffix("-dirty") {
+            Some(sha) => Revision::new(sha.to_string(), true),
+            None => Revision::new(git_describe, false),
+        };
+        if let Some(paths) = &metadata.dirty_paths {
+            revision.dirty = self.is_dirty(paths)?;
+        }
+
+        if metadata.branch {
+            // Outputs HEAD when HEAD is detached.
+            let branch = self.git(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+            revision.branch = (branch != "HEAD").then_some(branch);
+        }
+        if metadata.tag {
+            let tags = self.git(&["tag", "--points-at", "HEAD"])?;
+            revision.tag = tags.lines().next().map(str::to_string);
+        }
+        if metadata.describe {
+            revision.describe = Some(self.git(&["describe", "--tags", "--always"])?);
+        }
+        if metadata.commit_timestamp || metadata.author_date {
+            let dates = self.git(&["show", "--no-patch", "--format=%ct%n%cI%n%aI", "HEAD"])?;
+            let mut dates = dates.lines().map(str::to_string);
+            if metadata.commit_timestamp {
+                revision.commit_timestamp = dates.next().and_then(|t| t.parse().ok());
+                revision.commit_date = dates.next();
+            } else {
+                dates.nth(1);
+            }
+            if metadata.author_date {
+                revision.author_date = dates.next();
+            }
+        }
+
+        if metadata.crate_revision {
+            let sha = self.git(&["log", "-1", "--format=%H", "--", "."])?;
+            revision.crate_revision = (!sha.is_empty()).then_some(sha);
+        }
+        if metadata.crate_tree {
+            // Fails when the directory is not in the commit.
+            revision.crate_tree = match self.git(&["rev-parse", "--verify", "HEAD:./"]) {
+                Ok(tree) => Some(tree),
+                Err(Error::GitFailed { .. }) => None,
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(revision)
+    }
+
+    /// Whether tracked files under the paths, relative to the directory, have
+    /// changes in the index or working directory.
+    fn is_dirty(&self, paths: &[PathBuf]) -> Result<bool, Error> {
+        let paths = paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>();
+        let mut args = vec![
+            "--literal-pathspecs",
+            "status",
+            "--porcelain",
+            "--untracked-files=no",
+            "--",
+        ];
+        args.extend(paths.iter().map(|path| path.as_ref()));
+        Ok(!self.git(&args)?.is_empty())
+    }
+
+    /// Tracked files with changes in the index or working directory, relative
+    /// to the root of the repository, limited to the paths changes are
+    /// considered in.
+    pub(crate) fn modified_paths(&self, metadata: &Metadata) -> Result<Vec<PathBuf>, Error> {
+        let paths = metadata
+            .dirty_paths
+            .iter()
+            .flatten()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>();
+        // Changes between the commit and the working directory include those
+        // in the index.
+        let mut args = vec![
+            "--literal-pathspecs",
+            "diff",
+            "HEAD",
+            "--name-only",
+            "--no-renames",
+            "-z",
+            "--",
+        ];
+        args.extend(paths.iter().map(|path| path.as_ref()));
+        Ok(self
+            .git(&args)?
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    /// URLs of the remotes of the repository.
+    pub(crate) fn remote_urls(&self) -> Result<Vec<String>, Error> {
+        // Exits unsuccessfully when there are no remotes.
+        let config = match self.git(&["config", "--get-regexp", r"^remote\..*\.url$"]) {
+            Ok(config) => config,
+            Err(Error::GitFailed { .. }) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(config
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(_, url)| url.to_string())
+            .collect())
+    }
+
+    /// Whether the file, relative to the directory, is tracked in the index.
+    pub(crate) fn is_tracked(&self, current_dir: &Path, file: &str) -> Result<bool, Error> {
+        let args = [
+            "--literal-pathspecs",
+            "ls-files",
+            "--error-unmatch",
+            "--",
+            file,
+        ];
+        match git_output(current_dir, &args) {
+            Ok(_) => Ok(true),
+            Err(Error::GitFailed { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Files tracked in the index under the directory, relative to it.
+    pub(crate) fn tracked_files(&self, current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let files = git_output(current_dir, &["ls-files", "-z"])?;
+        Ok(files
+            .split(|b| *b == 0)
+            .filter(|file| !file.is_empty())
+            .map(path_from_bytes)
+            .collect())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, Error> {
+        git(&self.current_dir, args)
+    }
+}
+
+/// Run a git command in the directory, returning its trimmed stdout.
+fn git(current_dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let stdout = git_output(current_dir, args)?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+}
+
+/// Convert a path output by git to a path, without loss on platforms where
+/// paths are bytes, so that paths that are not valid unicode are not replaced
+/// with paths that do not exist.
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Run a git command in the directory, returning its stdout.
+fn git_output(current_dir: &Path, args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git")
+        .current_dir(current_dir)
+        .env("LC_ALL", "C")
+        .args(args)
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::GitNotFound,
+            _ => Error::Io(e),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if stderr.contains("not a git repository") {
+            return Err(Error::NotARepository);
+        }
+        return Err(Error::GitFailed {
+            status: output.status,
+            stderr,
+        });
+    }
+    Ok(output.stdout)
+}
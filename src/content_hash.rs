@@ -0,0 +1,54 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::Error;
+
+/// Prefix of revisions computed from the contents of the crate's files.
+pub(crate) const PREFIX: &str = "unknown+src.";
+
+/// Files of the package in the directory, relative to it, as selected by
+/// `cargo package`, which applies the `include` and `exclude` fields of the
+/// manifest. Files cargo generates when packaging, such as `Cargo.toml.orig`,
+/// are skipped when they do not exist.
+pub(crate) fn package_files(current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    // Build scripts are run with the path of the cargo running them.
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .current_dir(current_dir)
+        .args(["package", "--list", "--allow-dirty", "--offline", "--quiet"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::CargoFailed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let mut files = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .filter(|file| current_dir.join(file).is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Hash the paths and contents of the files, relative to the directory.
+pub(crate) fn hash(current_dir: &Path, files: &[PathBuf]) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    for file in files {
+        let contents = fs::read(current_dir.join(file))?;
+        // Separate the path from the contents, and prefix the contents with
+        // their length, so that different files cannot hash the same.
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    let hash = hasher.finalize();
+    Ok(hash.iter().map(|b| format!("{b:02x}")).collect())
+}
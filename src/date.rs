@@ -0,0 +1,81 @@
+/// Format a time in seconds since the unix epoch, in the time zone offset by
+/// the given number of seconds from UTC, in RFC 3339 format, matching the
+/// strict ISO 8601 format of git's `%cI` and `%aI` placeholders.
+#[cfg(any(feature = "gix", feature = "git2"))]
+pub(crate) fn format_rfc3339(seconds: i64, offset: i32) -> String {
+    let local = seconds + i64::from(offset);
+    let (year, month, day) = civil_from_days(local.div_euclid(86400));
+    let time = local.rem_euclid(86400);
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs() / 60;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}{sign}{:02}:{:02}",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        offset / 60,
+        offset % 60,
+    )
+}
+
+/// Parse a time in the strict ISO 8601 format of git's `%cI` and `%aI`
+/// placeholders, e.g. `2022-01-02T03:04:05+01:30`, into seconds since the unix
+/// epoch.
+pub(crate) fn parse_rfc3339(date: &str) -> Option<i64> {
+    let (date, time) = date.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (time, offset) = time.split_at(time.find(['+', '-', 'Z'])?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    let offset = match offset.strip_prefix('Z') {
+        Some("") => 0,
+        Some(_) => return None,
+        None => {
+            let (sign, offset) = offset.split_at(1);
+            let (hours, minutes) = offset.split_once(':')?;
+            let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            if sign == "-" {
+                -offset
+            } else {
+                offset
+            }
+        }
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Convert days since the unix epoch into a year, month, and day in the
+/// proleptic Gregorian calendar.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+#[cfg(any(feature = "gix", feature = "git2"))]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a year, month, and day in the proleptic Gregorian calendar into
+/// days since the unix epoch.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
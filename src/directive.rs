@@ -0,0 +1,150 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{files::is_hex_sha, Error};
+
+/// The format a value written in a cargo directive is expected to have.
+///
+/// Values are read from the repository, files in the crate and environment
+/// variables, and a line break in any would end the directive early and let
+/// the rest of the value be read as another directive, such as
+/// `cargo:rustc-link-arg`. Values not in their format are rejected rather
+/// than escaped, as cargo has no escaping for directives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// A commit hash, or another identifier of a revision, such as a content
+    /// hash, made of ASCII letters, digits, `+`, `-`, `.` and `_`.
+    Revision,
+    /// A full hex encoded object hash.
+    Sha,
+    /// A branch or tag name, or `git describe` output, following the rules of
+    /// `git check-ref-format`.
+    RefName,
+    /// A date in RFC 3339 format.
+    Date,
+    /// Any text without control characters, such as paths.
+    Text,
+}
+
+impl Format {
+    /// Whether the value is in the format.
+    pub(crate) fn matches(self, value: &str) -> bool {
+        match self {
+            Format::Revision => {
+                !value.is_empty()
+                    && value
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"+-._".contains(&b))
+            }
+            Format::Sha => is_hex_sha(value),
+            Format::RefName => {
+                Format::Text.matches(value)
+                    && !value.is_empty()
+                    && !value.contains(['~', '^', ':', '?', '*', '[', '\\', ' '])
+                    && !value.contains("..")
+                    && !value.contains("@{")
+            }
+            Format::Date => {
+                !value.is_empty()
+                    && value
+                        .bytes()
+                        .all(|b| b.is_ascii_digit() || b"-+:.TZ".contains(&b))
+            }
+            Format::Text => !value.chars().any(char::is_control),
+        }
+    }
+}
+
+/// Cargo directives, validated before any is written so that nothing is
+/// emitted for the revision if a value is rejected.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Directives {
+    lines: Vec<String>,
+}
+
+impl Directives {
+    pub(crate) fn rerun_if_changed(&mut self, path: &Path) -> Result<(), Error> {
+        // Paths that are not valid unicode cannot be written without loss, and
+        // a lossy path would never exist, rerunning the build script on every
+        // build.
+        match path.to_str() {
+            Some(value) if Format::Text.matches(value) => {
+                self.lines.push(format!("cargo:rerun-if-changed={value}"));
+                Ok(())
+            }
+            _ => Err(invalid("rerun-if-changed", &path.to_string_lossy())),
+        }
+    }
+
+    pub(crate) fn rerun_if_env_changed(&mut self, name: &str) -> Result<(), Error> {
+        check("rerun-if-env-changed", name, Format::Text)?;
+        self.lines
+            .push(format!("cargo:rerun-if-env-changed={name}"));
+        Ok(())
+    }
+
+    /// Set the environment variable, checking its value is in the format.
+    pub(crate) fn rustc_env(
+        &mut self,
+        name: &str,
+        value: &str,
+        format: Format,
+    ) -> Result<(), Error> {
+        if name.is_empty() || name.contains('=') || !Format::Text.matches(name) {
+            return Err(invalid("rustc-env", name));
+        }
+        check(&format!("rustc-env={name}"), value, format)?;
+        self.lines.push(format!("cargo:rustc-env={name}={value}"));
+        Ok(())
+    }
+
+    /// Add a warning, written with the directives.
+    pub(crate) fn warning(&mut self, message: &str) {
+        self.lines.push(warning_line(message));
+    }
+
+    pub(crate) fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        for line in &self.lines {
+            writeln!(w, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the value to write in the directive with the key is in the format.
+pub(crate) fn check(key: &str, value: &str, format: Format) -> Result<(), Error> {
+    if format.matches(value) {
+        Ok(())
+    } else {
+        Err(invalid(key, value))
+    }
+}
+
+fn invalid(key: &str, value: &str) -> Error {
+    Error::InvalidDirective {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// Write a warning, escaping control characters so that messages containing
+/// output of git or values of the repository stay on one line.
+pub(crate) fn warning(w: &mut impl Write, message: &str) -> io::Result<()> {
+    writeln!(w, "{}", warning_line(message))
+}
+
+fn warning_line(message: &str) -> String {
+    let message = message
+        .chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<String>();
+    format!("cargo:warning={message}")
+}
@@ -0,0 +1,44 @@
+use crate::{Error, GitRevision, Revision};
+
+/// Environment variable with the revision of crates neither published nor in a
+/// repository.
+pub(crate) const REVISION_ENV: &str = "CRATE_GIT_REVISION";
+
+/// Environment variable that requires a clean working directory when set to
+/// `1`, or allows a dirty one when set to `0`, overriding the builder.
+pub(crate) const REQUIRE_CLEAN_ENV: &str = "CRATE_GIT_REVISION_REQUIRE_CLEAN";
+
+/// Environment variables CI services set to the commit being built, in order
+/// of precedence.
+pub(crate) const CI_ENVS: &[&str] = &[
+    // GitHub Actions
+    "GITHUB_SHA",
+    // GitLab CI
+    "CI_COMMIT_SHA",
+    // Buildkite
+    "BUILDKITE_COMMIT",
+    // CircleCI
+    "CIRCLE_SHA1",
+    // Travis CI
+    "TRAVIS_COMMIT",
+    // Bitbucket Pipelines
+    "BITBUCKET_COMMIT",
+];
+
+/// Get the revision from the value of the environment variable, which is a
+/// full commit hash optionally followed by `-dirty`. Returns `None` if the
+/// variable is not set or empty.
+pub(crate) fn revision(name: &str, value: Option<String>) -> Result<Option<Revision>, Error> {
+    let value = match value {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+    let revision = GitRevision::parse(&value).map_err(|error| Error::InvalidEnvRevision {
+        name: name.to_string(),
+        error,
+    })?;
+    Ok(Some(Revision::new(
+        revision.sha().to_string(),
+        revision.is_dirty(),
+    )))
+}
@@ -0,0 +1,158 @@
+use std::{fmt, io, path::PathBuf, process::ExitStatus};
+
+use crate::{Backend, ObjectFormat, ParseGitRevisionError};
+
+/// Errors that can occur getting the git revision of a crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The `git` executable could not be found.
+    GitNotFound,
+    /// The crate is not in a git repository and is not a published crate.
+    NotARepository,
+    /// A `git` command exited unsuccessfully.
+    GitFailed { status: ExitStatus, stderr: String },
+    /// A file in the git directory could not be understood when reading the
+    /// repository without the `git` executable.
+    InvalidGitDir(PathBuf),
+    /// A ref could not be resolved to a commit when reading the repository
+    /// without the `git` executable, such as a branch with no commits.
+    RefNotFound(String),
+    /// A commit hash read from the repository is not a full hex encoded hash
+    /// of the object format of the repository.
+    InvalidSha {
+        sha: String,
+        object_format: ObjectFormat,
+    },
+    /// The backend cannot read repositories of the object format, such as the
+    /// libraries with SHA-256 repositories.
+    UnsupportedObjectFormat {
+        backend: Backend,
+        object_format: ObjectFormat,
+    },
+    /// The `.cargo_vcs_info.json` file of a published crate could not be
+    /// parsed.
+    InvalidVcsInfo(serde_json::Error),
+    /// `cargo package --list` exited unsuccessfully when listing the files to
+    /// hash.
+    #[cfg(feature = "content-hash")]
+    CargoFailed { status: ExitStatus, stderr: String },
+    /// An environment variable containing the revision is not a full commit
+    /// hash optionally followed by `-dirty`.
+    InvalidEnvRevision {
+        name: String,
+        error: ParseGitRevisionError,
+    },
+    /// The working directory is dirty and a clean one is required, with
+    /// [`Builder::require_clean`][crate::Builder::require_clean] or the
+    /// `CRATE_GIT_REVISION_REQUIRE_CLEAN` environment variable. Contains the
+    /// modified files relative to the root of the repository.
+    Dirty { paths: Vec<PathBuf> },
+    /// A clean working directory is required, but changes cannot be detected,
+    /// such as when reading the repository without the `git` executable.
+    CleanUnverified,
+    /// A value to write in a cargo directive is not in the format expected,
+    /// such as a ref name containing a line break, which could otherwise
+    /// inject other directives.
+    InvalidDirective { key: String, value: String },
+    /// An error from a [`RevisionSource`][crate::RevisionSource] outside this
+    /// crate.
+    Custom(Box<dyn std::error::Error + Send + Sync>),
+    /// An error reading the repository with libgit2.
+    #[cfg(feature = "git2")]
+    Git2(Box<dyn std::error::Error + Send + Sync>),
+    /// An error reading the repository with gitoxide.
+    #[cfg(feature = "gix")]
+    Gix(Box<dyn std::error::Error + Send + Sync>),
+    /// An I/O error occurred running `git` or writing to cargo.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::GitNotFound => write!(f, "git executable not found"),
+            Error::NotARepository => write!(
+                f,
+                "not a git repository and no .cargo_vcs_info.json file found"
+            ),
+            Error::GitFailed { status, stderr } => {
+                write!(f, "git failed with {status}: {}", stderr.trim())
+            }
+            Error::InvalidGitDir(path) => write!(f, "invalid git file {}", path.display()),
+            Error::RefNotFound(name) => write!(f, "git ref {name} not found"),
+            Error::InvalidVcsInfo(e) => write!(f, "invalid .cargo_vcs_info.json file: {e}"),
+            Error::InvalidEnvRevision { name, error } => {
+                write!(
+                    f,
+                    "invalid revision in {name} environment variable: {error}"
+                )
+            }
+            Error::Dirty { paths } => {
+                let paths = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "working directory is dirty and a clean build is required, modified files: {}",
+                    paths.join(", ")
+                )
+            }
+            Error::CleanUnverified => write!(
+                f,
+                "cannot verify the working directory is clean, changes are not detected without the git executable"
+            ),
+            Error::InvalidDirective { key, value } => {
+                write!(
+                    f,
+                    "refusing to emit cargo:{key} with invalid value {value:?}"
+                )
+            }
+            Error::InvalidSha { sha, object_format } => write!(
+                f,
+                "invalid commit hash {sha:?}, expected {} hex characters for {object_format}",
+                object_format.hex_len()
+            ),
+            Error::UnsupportedObjectFormat {
+                backend,
+                object_format,
+            } => write!(
+                f,
+                "the {backend:?} backend does not support the {object_format} object format of the repository"
+            ),
+            Error::Custom(e) => write!(f, "{e}"),
+            #[cfg(feature = "content-hash")]
+            Error::CargoFailed { status, stderr } => {
+                write!(f, "cargo package failed with {status}: {}", stderr.trim())
+            }
+            #[cfg(feature = "git2")]
+            Error::Git2(e) => write!(f, "{e}"),
+            #[cfg(feature = "gix")]
+            Error::Gix(e) => write!(f, "{e}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidVcsInfo(e) => Some(e),
+            Error::InvalidEnvRevision { error, .. } => Some(error),
+            Error::Custom(e) => Some(e.as_ref()),
+            #[cfg(feature = "git2")]
+            Error::Git2(e) => Some(e.as_ref()),
+            #[cfg(feature = "gix")]
+            Error::Gix(e) => Some(e.as_ref()),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
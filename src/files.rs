@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, read_to_string},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{revision::Metadata, Error, ObjectFormat, Revision};
+
+/// Maximum number of symbolic refs followed when resolving a ref, to guard
+/// against cycles.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// A git repository read directly from the files in its git directory,
+/// without the `git` executable.
+///
+/// Only refs are read, objects are not. The working directory is not compared
+/// with the index, so the revision is never reported as dirty, and the branch
+/// is the only metadata available.
+#[derive(Clone, Debug)]
+pub(crate) struct Repository {
+    /// The git directory of the working tree, e.g. `.git`, or
+    /// `.git/worktrees/<name>` for linked worktrees.
+    pub(crate) git_dir: PathBuf,
+    /// The git directory shared by all working trees, containing the refs.
+    pub(crate) common_dir: PathBuf,
+}
+
+impl Repository {
+    /// Find the repository containing the directory, searching the directory
+    /// and its parents for a `.git` directory or a `.git` file containing a
+    /// `gitdir:` pointer as used by worktrees and submodules.
+    pub(crate) fn discover(current_dir: &Path) -> Result<Self, Error> {
+        for dir in current_dir.ancestors() {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                let contents = read_to_string(&dot_git)?;
+                let path = contents
+                    .trim_end()
+                    .strip_prefix("gitdir: ")
+                    .ok_or_else(|| Error::InvalidGitDir(dot_git.clone()))?;
+                dir.join(path)
+            } else {
+                continue;
+            };
+            let common_dir = match read_to_string(git_dir.join("commondir")) {
+                Ok(path) => fs::canonicalize(git_dir.join(path.trim_end()))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => git_dir.clone(),
+                Err(e) => return Err(e.into()),
+            };
+            return Ok(Self {
+                git_dir,
+                common_dir,
+            });
+        }
+        Err(Error::NotARepository)
+    }
+
+    /// Files that change when the commit checked out changes: `HEAD`, the
+    /// loose files of the refs `HEAD` points to, and `packed-refs`. Paths are
+    /// relative to the directory if the git directory is.
+    ///
+    /// Cargo reruns build scripts on every build if a watched file does not
+    /// exist. A ref without a loose file, because it is packed or has no
+    /// commits, is watched through the nearest existing directory of its loose
+    /// file instead, which changes when the loose file is created.
+    pub(crate) fn head_files(&self, current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let resolved = Repository {
+            git_dir: current_dir.join(&self.git_dir),
+            common_dir: current_dir.join(&self.common_dir),
+        };
+        let mut files = vec![self.git_dir.join("HEAD")];
+        let mut value = resolved.read_loose("HEAD")?;
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let name = match value.as_deref().and_then(|v| v.strip_prefix("ref: ")) {
+                Some(name) => name.to_string(),
+                None => break,
+            };
+            let mut file = self.ref_dir(&name).join(&name);
+            value = resolved.read_loose(&name)?;
+            if value.is_none() {
+                while file.pop() && !current_dir.join(&file).is_dir() {}
+                files.push(file);
+                break;
+            }
+            files.push(file);
+        }
+        let packed_refs = self.common_dir.join("packed-refs");
+        if current_dir.join(&packed_refs).is_file() {
+            files.push(packed_refs);
+        }
+        Ok(files)
+    }
+
+    /// Get the revision of the commit checked out, and the branch if
+    /// requested.
+    pub(crate) fn revision(&self, metadata: &Metadata) -> Result<Revision, Error> {
+        let mut revision = Revision::new(self.resolve("HEAD")?, false);
+        if metadata.branch {
+            revision.branch = self
+                .read_loose("HEAD")?
+                .as_deref()
+                .and_then(|head| head.strip_prefix("ref: refs/heads/"))
+                .map(str::to_string);
+        }
+        Ok(revision)
+    }
+
+    /// Resolve a ref, following symbolic refs, to a commit hash.
+    fn resolve(&self, name: &str) -> Result<String, Error> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let value = match self.read_loose(&name)? {
+                Some(value) => value,
+                None => self
+                    .read_packed(&name)?
+                    .ok_or_else(|| Error::RefNotFound(name.clone()))?,
+            };
+            match value.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None if is_hex_sha(&value) => return Ok(value),
+                None => return Err(Error::InvalidGitDir(self.ref_dir(&name).join(&name))),
+            }
+        }
+        Err(Error::RefNotFound(name))
+    }
+
+    /// The object format of the repository, from the `extensions.objectFormat`
+    /// config.
+    pub(crate) fn object_format(&self) -> Result<ObjectFormat, Error> {
+        let path = self.common_dir.join("config");
+        let config = match read_to_string(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut section = String::new();
+        let mut value = None;
+        for line in config.lines().map(str::trim) {
+            if let Some(header) = line.strip_prefix('[') {
+                section = header.trim_end_matches(']').trim().to_ascii_lowercase();
+            } else if section == "extensions" {
+                match line.split_once('=') {
+                    Some((key, v)) if key.trim().eq_ignore_ascii_case("objectformat") => {
+                        value = Some(v.trim().to_string());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        ObjectFormat::from_config(value.as_deref()).ok_or(Error::InvalidGitDir(path))
+    }
+
+    /// Read a ref stored in its own file.
+    fn read_loose(&self, name: &str) -> Result<Option<String>, Error> {
+        match read_to_string(self.ref_dir(name).join(name)) {
+            Ok(value) => Ok(Some(value.trim_end().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read a ref from the `packed-refs` file.
+    fn read_packed(&self, name: &str) -> Result<Option<String>, Error> {
+        let packed_refs = match read_to_string(self.common_dir.join("packed-refs")) {
+            Ok(packed_refs) => packed_refs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(packed_refs
+            .lines()
+            // Skip the header and the peeled values of annotated tags.
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, ref_name)| *ref_name == name)
+            .map(|(sha, _)| sha.to_string()))
+    }
+
+    /// The directory a ref is stored in. `HEAD` and a few other refs are
+    /// specific to each working tree, all other refs are shared.
+    fn ref_dir(&self, name: &str) -> &Path {
+        let per_worktree = !name.starts_with("refs/")
+            || name.starts_with("refs/worktree/")
+            || name.starts_with("refs/bisect/")
+            || name.starts_with("refs/rewritten/");
+        if per_worktree {
+            &self.git_dir
+        } else {
+            &self.common_dir
+        }
+    }
+}
+
+/// Whether the value is a full hex encoded object hash.
+pub(crate) fn is_hex_sha(value: &str) -> bool {
+    matches!(value.len(), 40 | 64) && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use crate::{date::format_rfc3339, revision::Metadata, workdir, Error, ObjectFormat, Revision};
+
+/// A git repository read with libgit2.
+pub(crate) struct Repository {
+    repository: git2::Repository,
+    current_dir: PathBuf,
+    pub(crate) git_dir: PathBuf,
+    pub(crate) common_dir: PathBuf,
+}
+
+impl Repository {
+    /// Find the repository containing the directory.
+    pub(crate) fn discover(current_dir: &Path) -> Result<Self, Error> {
+        let repository = git2::Repository::discover(current_dir).map_err(|e| match e.code() {
+            git2::ErrorCode::NotFound => Error::NotARepository,
+            _ => git2_error(e),
+        })?;
+        // Remove the trailing separator libgit2 includes in the paths.
+        let git_dir = repository.path().components().collect::<PathBuf>();
+        let common_dir = repository.commondir().components().collect::<PathBuf>();
+        Ok(Self {
+            repository,
+            current_dir: current_dir.to_path_buf(),
+            git_dir,
+            common_dir,
+        })
+    }
+
+    /// The paths changes are considered in when determining whether the
+    /// working directory is dirty, relative to its root, or `None` for the
+    /// whole working directory.
+    fn dirty_paths(&self, metadata: &Metadata) -> Result<Option<Vec<PathBuf>>, Error> {
+        match (&metadata.dirty_paths, self.repository.workdir()) {
+            (Some(paths), Some(workdir)) => {
+                Ok(workdir::relative_paths(workdir, &self.current_dir, paths)?)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Tracked files with changes in the index or working directory, relative
+    /// to the root of the working directory, limited to the paths changes are
+    /// considered in.
+    pub(crate) fn modified_paths(&self, metadata: &Metadata) -> Result<Vec<PathBuf>, Error> {
+        // Match `git describe --dirty`, which considers changes to tracked
+        // files in the index or working directory, but not untracked files.
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        match self.dirty_paths(metadata)? {
+            Some(paths) if paths.is_empty() => return Ok(Vec::new()),
+            Some(paths) => {
+                options.disable_pathspec_match(true);
+                for path in paths {
+                    options.pathspec(path);
+                }
+            }
+            None => {}
+        }
+        let statuses = self
+            .repository
+            .statuses(Some(&mut options))
+            .map_err(git2_error)?;
+        Ok(statuses
+            .iter()
+            .filter(|status| status.status() != git2::Status::CURRENT)
+            .map(|status| PathBuf::from(String::from_utf8_lossy(status.path_bytes()).into_owned()))
+            .collect())
+    }
+
+    /// The path of the directory relative to the root of the working
+    /// directory, or `None` if it is not in the working directory.
+    fn prefix(&self, current_dir: &Path) -> Result<Option<PathBuf>, Error> {
+        match self.repository.workdir() {
+            Some(workdir) => Ok(workdir::prefix(workdir, current_dir)?),
+            None => Ok(None),
+        }
+    }
+
+    /// The object format of the repository, from the `extensions.objectFormat`
+    /// config.
+    pub(crate) fn object_format(&self) -> Result<ObjectFormat, Error> {
+        let config = self.repository.config().map_err(git2_error)?;
+        let value = match config.get_string("extensions.objectformat") {
+            Ok(value) => Some(value),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+            Err(e) => return Err(git2_error(e)),
+        };
+        ObjectFormat::from_config(value.as_deref())
+            .ok_or_else(|| Error::InvalidGitDir(self.common_dir.join("config")))
+    }
+
+    /// URLs of the remotes of the repository.
+    pub(crate) fn remote_urls(&self) -> Result<Vec<String>, Error> {
+        let names = self.repository.remotes().map_err(git2_error)?;
+        let mut urls = Vec::new();
+        for name in names.iter().flatten() {
+            let remote = self.repository.find_remote(name).map_err(git2_error)?;
+            urls.extend(remote.url().map(str::to_string));
+        }
+        Ok(urls)
+    }
+
+    /// Whether the file, relative to the directory, is tracked in the index.
+    pub(crate) fn is_tracked(&self, current_dir: &Path, file: &str) -> Result<bool, Error> {
+        let path = match self.prefix(current_dir)? {
+            Some(prefix) => prefix.join(file),
+            None => return Ok(false),
+        };
+        let index = self.repository.index().map_err(git2_error)?;
+        // Conflicted files have no entry at stage 0, only at stages 1 to 3.
+        Ok((0..=3).any(|stage| index.get_path(&path, stage).is_some()))
+    }
+
+    /// Files tracked in the index under the directory, relative to it.
+    pub(crate) fn tracked_files(&self, current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let prefix = match self.prefix(current_dir)? {
+            Some(prefix) => prefix,
+            None => return Ok(Vec::new()),
+        };
+        let index = self.repository.index().map_err(git2_error)?;
+        let mut files = index
+            .iter()
+            .filter_map(|entry| {
+                let path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+                path.strip_prefix(&prefix).ok().map(Path::to_path_buf)
+            })
+            .collect::<Vec<_>>();
+        // Conflicted files have an entry for each stage.
+        files.dedup();
+        Ok(files)
+    }
+
+    /// Get the revision of the commit checked out, and the metadata requested.
+    pub(crate) fn revision(&self, metadata: &Metadata) -> Result<Revision, Error> {
+        let head = self.repository.head().map_err(|e| match e.code() {
+            git2::ErrorCode::UnbornBranch => Error::RefNotFound("HEAD".to_string()),
+            _ => git2_error(e),
+        })?;
+        let commit = head.peel_to_commit().map_err(git2_error)?;
+
+        let dirty = !self.modified_paths(metadata)?.is_empty();
+
+        let mut revision = Revision::new(commit.id().to_string(), dirty);
+
+        if metadata.branch && head.is_branch() {
+            revision.branch = head.shorthand().map(str::to_string);
+        }
+        if metadata.tag {
+            // Match `git tag --points-at`, which lists tags in order of name.
+            let mut tags = Vec::new();
+            for reference in self
+                .repository
+                .references_glob("refs/tags/*")
+                .map_err(git2_error)?
+            {
+                let reference = reference.map_err(git2_error)?;
+                let points_at = reference
+                    .peel_to_commit()
+                    .map_or(false, |c| c.id() == commit.id());
+                if points_at {
+                    tags.extend(reference.shorthand().map(str::to_string));
+                }
+            }
+            tags.sort();
+            revision.tag = tags.into_iter().next();
+        }
+        if metadata.describe {
+            let describe = commit
+                .as_object()
+                .describe(
+                    git2::DescribeOptions::new()
+                        .describe_tags()
+                        .show_commit_oid_as_fallback(true),
+                )
+                .and_then(|d| d.format(None))
+                .map_err(git2_error)?;
+            revision.describe = Some(describe);
+        }
+        if metadata.commit_timestamp {
+            let time = commit.committer().when();
+            revision.commit_timestamp = Some(time.seconds());
+            revision.commit_date = Some(format_rfc3339(time.seconds(), time.offset_minutes() * 60));
+        }
+        if metadata.author_date {
+            let time = commit.author().when();
+            revision.author_date = Some(format_rfc3339(time.seconds(), time.offset_minutes() * 60));
+        }
+        if metadata.crate_revision || metadata.crate_tree {
+            if let Some(prefix) = self.prefix(&self.current_dir)? {
+                let tree = tree_entry_id(&commit, &prefix)?;
+                if metadata.crate_revision {
+                    revision.crate_revision = last_commit_changing(commit, &prefix, tree)?;
+                }
+                if metadata.crate_tree {
+                    revision.crate_tree = tree.map(|id| id.to_string());
+                }
+            }
+        }
+
+        Ok(revision)
+    }
+}
+
+/// The hash of the last commit that changed the path, starting from the
+/// commit where the path has the entry, like `git log -1 -- <path>`, which
+/// follows the first parent the path is unchanged in.
+fn last_commit_changing(
+    mut commit: git2::Commit<'_>,
+    path: &Path,
+    entry: Option<git2::Oid>,
+) -> Result<Option<String>, Error> {
+    loop {
+        let mut unchanged_in = None;
+        for parent in commit.parents() {
+            if tree_entry_id(&parent, path)? == entry {
+                unchanged_in = Some(parent);
+                break;
+            }
+        }
+        match unchanged_in {
+            Some(parent) => commit = parent,
+            // A root commit changes the path if it contains it.
+            None if commit.parent_count() == 0 && entry.is_none() => return Ok(None),
+            None => return Ok(Some(commit.id().to_string())),
+        }
+    }
+}
+
+/// The hash of the tree or blob at the path in the commit, where an empty path
+/// is the root tree.
+fn tree_entry_id(commit: &git2::Commit<'_>, path: &Path) -> Result<Option<git2::Oid>, Error> {
+    if path.as_os_str().is_empty() {
+        return Ok(Some(commit.tree_id()));
+    }
+    let tree = commit.tree().map_err(git2_error)?;
+    match tree.get_path(path) {
+        Ok(entry) => Ok(Some(entry.id())),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(git2_error(e)),
+    }
+}
+
+fn git2_error(e: git2::Error) -> Error {
+    Error::Git2(Box::new(e))
+}
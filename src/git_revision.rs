@@ -0,0 +1,201 @@
+use std::fmt;
+
+/// A git revision embedded in a crate, parsed from the value of the
+/// `GIT_REVISION` environment variable.
+///
+/// Intended to be used by the crate at runtime, or in `const` items, rather
+/// than in the build script. The revision is a full hex encoded commit hash
+/// of 40 (SHA-1) or 64 (SHA-256) characters, optionally followed by the
+/// default `-dirty` suffix.
+///
+/// ### Examples
+///
+/// ```rust
+/// use crate_git_revision::GitRevision;
+///
+/// const GIT_REVISION: GitRevision =
+///     GitRevision::new("0c5255b6f47649305fcb68edccb285510aec71a7-dirty");
+///
+/// assert_eq!(GIT_REVISION.sha(), "0c5255b6f47649305fcb68edccb285510aec71a7");
+/// assert_eq!(GIT_REVISION.short(7), "0c5255b");
+/// assert!(GIT_REVISION.is_dirty());
+/// ```
+///
+/// In a crate using [`init`][crate::init] in its build script:
+///
+/// ```ignore
+/// pub const GIT_REVISION: GitRevision = GitRevision::new(env!("GIT_REVISION"));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GitRevision<'a> {
+    revision: &'a str,
+    sha_len: usize,
+}
+
+impl<'a> GitRevision<'a> {
+    /// Suffix of revisions of dirty working directories.
+    pub const DIRTY_SUFFIX: &'static str = "-dirty";
+
+    /// Create a revision from its string form.
+    ///
+    /// ### Panics
+    ///
+    /// If the revision is invalid. When used in a `const` item, an invalid
+    /// revision is a compile error. Use [`GitRevision::parse`] to handle
+    /// invalid revisions.
+    pub const fn new(revision: &'a str) -> Self {
+        match Self::parse(revision) {
+            Ok(revision) => revision,
+            Err(_) => panic!("invalid git revision"),
+        }
+    }
+
+    /// Parse a revision from its string form.
+    pub const fn parse(revision: &'a str) -> Result<Self, ParseGitRevisionError> {
+        let bytes = revision.as_bytes();
+        let suffix = Self::DIRTY_SUFFIX.as_bytes();
+        let sha_len = if ends_with(bytes, suffix) {
+            bytes.len() - suffix.len()
+        } else {
+            bytes.len()
+        };
+        if sha_len != 40 && sha_len != 64 {
+            return Err(ParseGitRevisionError::InvalidLength(sha_len));
+        }
+        let mut i = 0;
+        while i < sha_len {
+            if !matches!(bytes[i], b'0'..=b'9' | b'a'..=b'f') {
+                return Err(ParseGitRevisionError::InvalidCharacter(i));
+            }
+            i += 1;
+        }
+        Ok(Self { revision, sha_len })
+    }
+
+    /// The full hex encoded commit hash, without the dirty suffix.
+    pub fn sha(&self) -> &'a str {
+        &self.revision[..self.sha_len]
+    }
+
+    /// The first `len` characters of the commit hash.
+    pub fn short(&self, len: usize) -> &'a str {
+        &self.revision[..len.min(self.sha_len)]
+    }
+
+    /// Whether the working directory was dirty when the crate was built.
+    pub fn is_dirty(&self) -> bool {
+        self.sha_len != self.revision.len()
+    }
+
+    /// The revision in its string form, including the dirty suffix.
+    pub fn as_str(&self) -> &'a str {
+        self.revision
+    }
+
+    /// The object format of the repository, from the length of the hash.
+    pub const fn object_format(&self) -> ObjectFormat {
+        if self.sha_len == ObjectFormat::Sha256.hex_len() {
+            ObjectFormat::Sha256
+        } else {
+            ObjectFormat::Sha1
+        }
+    }
+}
+
+impl fmt::Display for GitRevision<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.revision)
+    }
+}
+
+/// The hash function a git repository names its objects with, set with
+/// `git init --object-format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ObjectFormat {
+    /// SHA-1, with hashes of 40 hex characters. The default.
+    Sha1,
+    /// SHA-256, with hashes of 64 hex characters.
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// The name of the format, as in the `extensions.objectFormat` config and
+    /// `GIT_OBJECT_FORMAT`: `sha1` or `sha256`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// The number of characters in a hex encoded hash.
+    pub const fn hex_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 40,
+            ObjectFormat::Sha256 => 64,
+        }
+    }
+
+    /// Whether the value is a full hex encoded hash in the format.
+    pub(crate) fn is_hex_sha(self, value: &str) -> bool {
+        value.len() == self.hex_len() && value.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    /// The format named in the `extensions.objectFormat` config, which is
+    /// SHA-1 when not set.
+    pub(crate) fn from_config(value: Option<&str>) -> Option<Self> {
+        match value.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("sha1") => Some(ObjectFormat::Sha1),
+            Some("sha256") => Some(ObjectFormat::Sha256),
+            Some(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error parsing a [`GitRevision`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseGitRevisionError {
+    /// The commit hash has a length other than 40 or 64 characters.
+    InvalidLength(usize),
+    /// The commit hash contains a character that is not lowercase hex, at the
+    /// index.
+    InvalidCharacter(usize),
+}
+
+impl fmt::Display for ParseGitRevisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseGitRevisionError::InvalidLength(len) => {
+                write!(f, "invalid git revision length {len}, expected 40 or 64")
+            }
+            ParseGitRevisionError::InvalidCharacter(i) => {
+                write!(f, "invalid git revision character at index {i}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseGitRevisionError {}
+
+const fn ends_with(bytes: &[u8], suffix: &[u8]) -> bool {
+    if bytes.len() < suffix.len() {
+        return false;
+    }
+    let offset = bytes.len() - suffix.len();
+    let mut i = 0;
+    while i < suffix.len() {
+        if bytes[offset + i] != suffix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{date::format_rfc3339, revision::Metadata, workdir, Error, ObjectFormat, Revision};
+
+/// A git repository read with gitoxide.
+pub(crate) struct Repository {
+    repository: gix::Repository,
+    current_dir: PathBuf,
+    pub(crate) git_dir: PathBuf,
+    pub(crate) common_dir: PathBuf,
+}
+
+impl Repository {
+    /// Find the repository containing the directory.
+    pub(crate) fn discover(current_dir: &Path) -> Result<Self, Error> {
+        let repository = gix::discover(current_dir).map_err(|e| match e {
+            gix::discover::Error::Discover(_) => Error::NotARepository,
+            e => gix_error(e),
+        })?;
+        let git_dir = repository.git_dir().to_path_buf();
+        // The common dir of linked worktrees is relative to the git dir, e.g.
+        // `.git/worktrees/<name>/../..`.
+        let common_dir = if repository.common_dir() == repository.git_dir() {
+            git_dir.clone()
+        } else {
+            fs::canonicalize(repository.common_dir())?
+        };
+        Ok(Self {
+            repository,
+            current_dir: current_dir.to_path_buf(),
+            git_dir,
+            common_dir,
+        })
+    }
+
+    /// The paths changes are considered in when determining whether the
+    /// working directory is dirty, relative to its root, or `None` for the
+    /// whole working directory.
+    fn dirty_paths(&self, metadata: &Metadata) -> Result<Option<Vec<PathBuf>>, Error> {
+        match (&metadata.dirty_paths, self.repository.workdir()) {
+            (Some(paths), Some(workdir)) => {
+                Ok(workdir::relative_paths(workdir, &self.current_dir, paths)?)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Changes to tracked files under the paths, relative to the root of the
+    /// working directory, or in the whole working directory if there are no
+    /// paths.
+    fn status(&self, paths: &[PathBuf]) -> Result<gix::status::Iter, Error> {
+        // Match the paths literally, from the root of the working directory
+        // rather than the current directory of the process.
+        let patterns = paths.iter().map(|path| {
+            let path = gix::path::to_unix_separators_on_windows(gix::path::into_bstr(path));
+            let mut pattern = gix::bstr::BString::from(":(top,literal)");
+            pattern.extend_from_slice(&path);
+            pattern
+        });
+        self.repository
+            .status(gix::progress::Discard)
+            .map_err(gix_error)?
+            .untracked_files(gix::status::UntrackedFiles::None)
+            .index_worktree_submodules(gix::status::Submodule::AsConfigured { check_dirty: true })
+            .into_iter(patterns)
+            .map_err(gix_error)
+    }
+
+    /// Tracked files with changes in the index or working directory, relative
+    /// to the root of the working directory, limited to the paths changes are
+    /// considered in.
+    pub(crate) fn modified_paths(&self, metadata: &Metadata) -> Result<Vec<PathBuf>, Error> {
+        let paths = match self.dirty_paths(metadata)? {
+            Some(paths) if paths.is_empty() => return Ok(Vec::new()),
+            paths => paths.unwrap_or_default(),
+        };
+        let mut modified = Vec::new();
+        for item in self.status(&paths)? {
+            let item = item.map_err(gix_error)?;
+            modified.push(PathBuf::from(item.location().to_string()));
+        }
+        // A file with changes in both the index and the working directory is
+        // reported twice.
+        modified.sort();
+        modified.dedup();
+        Ok(modified)
+    }
+
+    /// The path of the directory relative to the root of the working
+    /// directory, or `None` if it is not in the working directory.
+    fn prefix(&self, current_dir: &Path) -> Result<Option<PathBuf>, Error> {
+        match self.repository.workdir() {
+            Some(workdir) => Ok(workdir::prefix(workdir, current_dir)?),
+            None => Ok(None),
+        }
+    }
+
+    /// The object format of the repository.
+    pub(crate) fn object_format(&self) -> Result<ObjectFormat, Error> {
+        match self.repository.object_hash() {
+            gix::hash::Kind::Sha1 => Ok(ObjectFormat::Sha1),
+            // Kinds added to gix-hash, which may be enabled by other crates.
+            #[allow(unreachable_patterns)]
+            kind => Err(gix_error(format!("unsupported object format {kind:?}"))),
+        }
+    }
+
+    /// URLs of the remotes of the repository.
+    pub(crate) fn remote_urls(&self) -> Vec<String> {
+        let config = self.repository.config_snapshot();
+        self.repository
+            .remote_names()
+            .iter()
+            .filter_map(|name| config.string(format!("remote.{name}.url").as_str()))
+            .map(|url| url.to_string())
+            .collect()
+    }
+
+    /// Whether the file, relative to the directory, is tracked in the index.
+    pub(crate) fn is_tracked(&self, current_dir: &Path, file: &str) -> Result<bool, Error> {
+        let path = match self.prefix(current_dir)? {
+            Some(prefix) => prefix.join(file),
+            None => return Ok(false),
+        };
+        let path = gix::path::to_unix_separators_on_windows(gix::path::into_bstr(path));
+        let index = self.repository.index_or_empty().map_err(gix_error)?;
+        Ok(index.entry_by_path(path.as_ref()).is_some())
+    }
+
+    /// Files tracked in the index under the directory, relative to it.
+    pub(crate) fn tracked_files(&self, current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let prefix = match self.prefix(current_dir)? {
+            Some(prefix) => prefix,
+            None => return Ok(Vec::new()),
+        };
+        let index = self.repository.index_or_empty().map_err(gix_error)?;
+        let mut files = index
+            .entries()
+            .iter()
+            .filter_map(|entry| {
+                let path = PathBuf::from(entry.path(&index).to_string());
+                path.strip_prefix(&prefix).ok().map(Path::to_path_buf)
+            })
+            .collect::<Vec<_>>();
+        // Conflicted files have an entry for each stage.
+        files.dedup();
+        Ok(files)
+    }
+
+    /// Get the revision of the commit checked out, and the metadata requested.
+    pub(crate) fn revision(&self, metadata: &Metadata) -> Result<Revision, Error> {
+        let mut head = self.repository.head().map_err(gix_error)?;
+        if head.is_unborn() {
+            let name = head
+                .referent_name()
+                .map_or_else(|| "HEAD".to_string(), |name| name.as_bstr().to_string());
+            return Err(Error::RefNotFound(name));
+        }
+        let branch = head.referent_name().map(|name| name.shorten().to_string());
+        let commit = head.peel_to_commit().map_err(gix_error)?;
+
+        // Like `git describe --dirty`, untracked files are not considered.
+        let dirty = match self.dirty_paths(metadata)? {
+            None => self.repository.is_dirty().map_err(gix_error)?,
+            Some(paths) if paths.is_empty() => false,
+            Some(paths) => self
+                .status(&paths)?
+                .next()
+                .transpose()
+                .map_err(gix_error)?
+                .is_some(),
+        };
+
+        let mut revision = Revision::new(commit.id.to_string(), dirty);
+
+        if metadata.branch {
+            revision.branch = branch;
+        }
+        if metadata.tag {
+            // Match `git tag --points-at`, which lists tags in order of name.
+            let mut tags = Vec::new();
+            let references = self.repository.references().map_err(gix_error)?;
+            for reference in references.tags().map_err(gix_error)? {
+                let mut reference = reference.map_err(gix_error)?;
+                let points_at = reference.peel_to_id().map_or(false, |id| id == commit.id);
+                if points_at {
+                    tags.push(reference.name().shorten().to_string());
+                }
+            }
+            tags.sort();
+            revision.tag = tags.into_iter().next();
+        }
+        if metadata.describe {
+            let describe = commit
+                .describe()
+                .names(gix::commit::describe::SelectRef::AllTags)
+                .id_as_fallback(true)
+                .format()
+                .map_err(gix_error)?;
+            revision.describe = Some(describe.to_string());
+        }
+        if metadata.commit_timestamp {
+            let time = commit.time().map_err(gix_error)?;
+            revision.commit_timestamp = Some(time.seconds);
+            revision.commit_date = Some(format_rfc3339(time.seconds, time.offset));
+        }
+        if metadata.author_date {
+            let author = commit.author().map_err(gix_error)?;
+            let time = author.time().map_err(gix_error)?;
+            revision.author_date = Some(format_rfc3339(time.seconds, time.offset));
+        }
+        if metadata.crate_revision || metadata.crate_tree {
+            if let Some(prefix) = self.prefix(&self.current_dir)? {
+                let tree = tree_entry_id(&commit, &prefix)?;
+                if metadata.crate_revision {
+                    revision.crate_revision = last_commit_changing(commit, &prefix, tree)?;
+                }
+                if metadata.crate_tree {
+                    revision.crate_tree = tree.map(|id| id.to_string());
+                }
+            }
+        }
+
+        Ok(revision)
+    }
+}
+
+/// The hash of the last commit that changed the path, starting from the
+/// commit where the path has the entry, like `git log -1 -- <path>`, which
+/// follows the first parent the path is unchanged in.
+fn last_commit_changing(
+    mut commit: gix::Commit<'_>,
+    path: &Path,
+    entry: Option<gix::ObjectId>,
+) -> Result<Option<String>, Error> {
+    loop {
+        let mut unchanged_in = None;
+        let mut has_parents = false;
+        for parent in commit.parent_ids() {
+            has_parents = true;
+            let parent = parent
+                .object()
+                .map_err(gix_error)?
+                .try_into_commit()
+                .map_err(gix_error)?;
+            if tree_entry_id(&parent, path)? == entry {
+                unchanged_in = Some(parent);
+                break;
+            }
+        }
+        match unchanged_in {
+            Some(parent) => commit = parent,
+            // A root commit changes the path if it contains it.
+            None if !has_parents && entry.is_none() => return Ok(None),
+            None => return Ok(Some(commit.id.to_string())),
+        }
+    }
+}
+
+/// The hash of the tree or blob at the path in the commit, where an empty path
+/// is the root tree.
+fn tree_entry_id(commit: &gix::Commit<'_>, path: &Path) -> Result<Option<gix::ObjectId>, Error> {
+    if path.as_os_str().is_empty() {
+        return Ok(Some(commit.tree_id().map_err(gix_error)?.detach()));
+    }
+    let entry = commit
+        .tree()
+        .map_err(gix_error)?
+        .lookup_entry_by_path(path)
+        .map_err(gix_error)?;
+    Ok(entry.map(|entry| entry.object_id()))
+}
+
+fn gix_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::Gix(e.into())
+}
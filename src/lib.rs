@@ -4,13 +4,92 @@
 //! is occurring in, as well as when `cargo install` or depending on a crate
 //! published to crates.io.
 //!
-//! It extracts the git revision in two ways:
+//! It extracts the git revision in three ways:
 //! - From the `.cargo_vcs_info.json` file embedded in published crates.
 //! - From the git repository the build is occurring from in unpublished crates.
+//! - From a `.git_archival.txt` file substituted by `git archive`, in source
+//!   archives of the repository such as GitHub's source code downloads.
+//!
+//! To support source archives, add a `.git_archival.txt` file to the root of the
+//! repository containing:
+//!
+//! ```text
+//! node: $Format:%H$
+//! node-date: $Format:%cI$
+//! describe-name: $Format:%(describe:tags=true)$
+//! ref-names: $Format:%D$
+//! ```
+//!
+//! And have git substitute the placeholders when archiving by adding the
+//! following to the repository's `.gitattributes` file:
+//!
+//! ```text
+//! .git_archival.txt export-subst
+//! ```
+//!
+//! The `CRATE_GIT_REVISION` environment variable, set to a full commit hash
+//! optionally followed by `-dirty`, is the revision of crates that are neither
+//! published nor in a git repository, such as when building in a container
+//! without the `.git` directory. It is also used, with a warning, when the
+//! repository cannot be opened, such as when `git` refuses a repository owned by
+//! another user. It is not used for crates in a repository that can be read, so
+//! that git and path dependencies from other repositories keep their own
+//! revision. [`Builder::ci_revision`] also reads the commit from the environment
+//! variables of common CI services when no other source is available.
+//!
+//! When the `git` executable is not available, such as in minimal build
+//! environments, the revision is read from the refs in the `.git` directory,
+//! and dirty working directories are not detected. See [`Backend`].
+//!
+//! The `gix` and `git2` features add backends that read the repository with
+//! [gitoxide](https://crates.io/crates/gix) or
+//! [libgit2](https://crates.io/crates/git2) instead of the `git` executable.
+//! Select them with [`Builder::backend`]. The `gix` feature requires Rust 1.82 or
+//! later, newer than the minimum supported Rust version of the crate.
+//!
+//! The `content-hash` feature adds `Builder::content_hash_fallback`, which
+//! embeds a hash of the crate's files when building from a source tarball with
+//! neither a git repository nor a `.cargo_vcs_info.json` file.
+//!
+//! [`Builder::detect_vendored`] ignores the repository containing the crate, with
+//! a cargo warning rather than embed its revision, when the crate appears to be
+//! vendored or copied into it, because its `repository` does not match the
+//! remotes. [`Builder::detect_untracked`] also ignores it when the crate's
+//! `Cargo.toml` is not tracked in it.
+//!
+//! When no revision is found, such as when a crate is installed with `cargo
+//! install` from a vendored source tarball without either, `GIT_REVISION` is
+//! set to `unknown` and the error is reported as a cargo warning, so that the
+//! crate still compiles. [`Builder::fallback`] configures this.
+//!
+//! To avoid shipping builds of changes that are not committed,
+//! [`Builder::require_clean`] fails release builds, or all builds, when the
+//! working directory is dirty, listing the modified files, or when changes cannot
+//! be detected without the `git` executable. Setting
+//! `CRATE_GIT_REVISION_REQUIRE_CLEAN` to `1` or `0` overrides it.
+//!
+//! The sources of the revision, and the order they are tried in, can be
+//! replaced with [`Builder::sources`], including with sources outside this
+//! crate that implement [`RevisionSource`].
 //!
 //! Injects an environment variable `GIT_REVISION` into the build that contains
 //! the full git revision, with a `-dirty` suffix if the working directory is
-//! dirty.
+//! dirty, or if a published crate was published with `--allow-dirty`. Published
+//! crates also get `GIT_PATH_IN_VCS`, the path of the crate in its repository.
+//!
+//! Additional environment variables can be enabled with [`Builder`], such as
+//! `GIT_BRANCH`, `GIT_TAG`, `GIT_DESCRIBE`, `GIT_COMMIT_TIMESTAMP`,
+//! `GIT_COMMIT_TIMESTAMP_RFC3339`, `GIT_COMMIT_AUTHOR_DATE`, `GIT_REVISION_SHORT`,
+//! `GIT_DIRTY`, `GIT_OBJECT_FORMAT` (`sha1` or `sha256`), and
+//! `GIT_REVISION_SOURCE`, which tells whether the crate was built from a
+//! registry, a git checkout, a path dependency or a workspace. They are off by
+//! default, and only looked up when enabled.
+//! In monorepos, `GIT_CRATE_REVISION` and `GIT_CRATE_TREE` identify the last
+//! commit and the tree of the crate's own directory, which stay the same across
+//! commits to other crates.
+//!
+//! [`Builder::generate_source`] also writes the revision and metadata as typed
+//! constants to `git_revision.rs` in `OUT_DIR`, for crates to `include!`.
 //!
 //! Requires the use of a build.rs build script. See [Build Scripts]() for more
 //! details on how Rust build scripts work.
@@ -28,7 +107,7 @@
 //!
 //! Add the following to the crate's `build.rs` file:
 //!
-//! ```rust
+//! ```no_run
 //! crate_git_revision::init();
 //! ```
 //!
@@ -37,105 +116,113 @@
 //! ```ignore
 //! pub const GIT_REVISION: &str = env!("GIT_REVISION");
 //! ```
+//!
+//! Or, with the crate also added to `[dependencies]`, use [`git_revision!`],
+//! which fails with an error explaining how to set up the build script if
+//! `GIT_REVISION` is not set, or [`build_info!`] for the revision and metadata
+//! together. Parse the revision into a [`GitRevision`] to access the commit hash
+//! and whether it is dirty:
+//!
+//! ```ignore
+//! use crate_git_revision::GitRevision;
+//! pub const GIT_REVISION: &str = crate_git_revision::git_revision!();
+//! pub const PARSED: GitRevision = GitRevision::new(GIT_REVISION);
+//! ```
+//!
+//! Use [`Builder`] to change the environment variable name, the suffix used
+//! for dirty working directories, or the directory of the crate:
+//!
+//! ```no_run
+//! crate_git_revision::Builder::new()
+//!     .env_name("MY_GIT_REVISION")
+//!     .dirty_suffix("-modified")
+//!     .emit();
+//! ```
 
-use std::{fs::read_to_string, path::Path, process::Command, str};
+mod archival;
+mod build_info;
+mod builder;
+mod command;
+#[cfg(feature = "content-hash")]
+mod content_hash;
+mod date;
+mod directive;
+mod env;
+mod error;
+mod files;
+#[cfg(feature = "git2")]
+mod git2_backend;
+mod git_revision;
+#[cfg(feature = "gix")]
+mod gix_backend;
+mod macros;
+mod revision;
+mod source;
+mod source_kind;
+pub mod sources;
+mod vendored;
+#[cfg(any(feature = "gix", feature = "git2"))]
+mod workdir;
+
+pub use build_info::BuildInfo;
+pub use builder::{Backend, Builder, Fallback, RequireClean};
+pub use error::Error;
+pub use git_revision::{GitRevision, ObjectFormat, ParseGitRevisionError};
+pub use revision::Revision;
+pub use source_kind::SourceKind;
+pub use sources::{RevisionSource, SourceContext};
 
 /// Initialize the GIT_REVISION environment variable with the git revision of
 /// the current crate.
 ///
 /// Intended to be called from within a build script, `build.rs` file, for the
 /// crate.
+///
+/// If the revision cannot be found, the error is reported as a cargo warning
+/// and the revision is set to `unknown`. Use [`Builder`] to configure the
+/// environment variable name, dirty suffix, fallback, or the directory of the
+/// crate.
 pub fn init() {
-    let _res = __init(&mut std::io::stdout(), &std::env::current_dir().unwrap());
+    Builder::new().emit();
 }
 
-fn __init(w: &mut impl std::io::Write, current_dir: &Path) -> std::io::Result<()> {
-    let mut git_sha: Option<String> = None;
-
-    // Read the git revision from the JSON file embedded by cargo publish. This
-    // will get the version from published crates.
-    if let Ok(vcs_info) = read_to_string(current_dir.join(".cargo_vcs_info.json")) {
-        let vcs_info: Result<CargoVcsInfo, _> = serde_json::from_str(&vcs_info);
-        if let Ok(vcs_info) = vcs_info {
-            git_sha = Some(vcs_info.git.sha1);
-        }
-    }
-
-    // Read the git revision from the git repository containing the code being
-    // built.
-    if git_sha.is_none() {
-        match Command::new("git")
-            .current_dir(current_dir)
-            .arg("rev-parse")
-            .arg("--git-dir")
-            .output()
-            .map(|o| o.stdout)
-        {
-            Err(e) => {
-                writeln!(
-                    w,
-                    "cargo:warning=Error getting git directory to get git revision: {e:?}"
-                )?;
-            }
-            Ok(git_dir) => {
-                let git_dir = String::from_utf8_lossy(&git_dir);
-                let git_dir = git_dir.trim();
-
-                // Require the build script to rerun if relavent git state changes which
-                // changes the current git commit.
-                //  - .git/index: Changes if the index/staged files changes, which will
-                //  cause the repo to be dirty.
-                //  - .git/HEAD: Changes if the ref currently in the working directory,
-                //  and potentially the commit, to change.
-                //  - .git/refs: Changes to any files in refs could cause the current
-                //  commit to have changed if the ref in .git/HEAD is changed.
-                // Note: That changes in the above files may not result in material
-                // changes to the crate, but changes in any should invalidate the
-                // revision since the revision can be changed by any of the above.
-                writeln!(w, "cargo:rerun-if-changed={git_dir}/index")?;
-                writeln!(w, "cargo:rerun-if-changed={git_dir}/HEAD")?;
-                writeln!(w, "cargo:rerun-if-changed={git_dir}/refs")?;
-
-                match Command::new("git")
-                    .current_dir(current_dir)
-                    .arg("describe")
-                    .arg("--always")
-                    .arg("--exclude='*'")
-                    .arg("--long")
-                    .arg("--abbrev=1000")
-                    .arg("--dirty")
-                    .output()
-                    .map(|o| o.stdout)
-                {
-                    Err(e) => {
-                        writeln!(
-                            w,
-                            "cargo:warning=Error getting git revision from {current_dir:?}: {e:?}"
-                        )?;
-                    }
-                    Ok(git_describe) => {
-                        git_sha = str::from_utf8(&git_describe).ok().map(str::to_string);
-                    }
-                }
-            }
-        }
-    }
-
-    if let Some(git_sha) = git_sha {
-        writeln!(w, "cargo:rustc-env=GIT_REVISION={git_sha}")?;
-    }
+/// Initialize the GIT_REVISION environment variable with the git revision of
+/// the current crate, returning an error if the revision could not be found.
+///
+/// Unlike [`init`], which reports errors as cargo warnings and lets the build
+/// continue, the build script decides how to handle errors:
+///
+/// ```no_run
+/// if let Err(e) = crate_git_revision::try_init() {
+///     println!("cargo:rustc-env=GIT_REVISION=unknown");
+///     println!("cargo:warning=Error getting git revision: {e}");
+/// }
+/// ```
+pub fn try_init() -> Result<Revision, Error> {
+    Builder::new().try_emit()
+}
 
-    Ok(())
+#[cfg(test)]
+fn __init(w: &mut impl std::io::Write, current_dir: &std::path::Path) -> Result<(), Error> {
+    Builder::new()
+        .manifest_dir(current_dir)
+        .emit_to_with_env(w, &|_| None)
 }
 
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Default)]
 struct CargoVcsInfo {
     git: CargoVcsInfoGit,
+    #[serde(default)]
+    path_in_vcs: Option<String>,
 }
 
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Default)]
 struct CargoVcsInfoGit {
     sha1: String,
+    /// Set when the crate was published with `--allow-dirty` and the working
+    /// directory was dirty.
+    #[serde(default)]
+    dirty: bool,
 }
 
 mod test;
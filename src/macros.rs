@@ -0,0 +1,74 @@
+/// Expands to the git revision embedded by [`init`][crate::init], as a
+/// `&'static str`.
+///
+/// Takes the name of the environment variable if it was changed with
+/// [`Builder::env_name`][crate::Builder::env_name]. Fails to compile with an
+/// error explaining how to set up the build script if the environment variable
+/// is not set.
+///
+/// ### Examples
+///
+/// ```ignore
+/// pub const GIT_REVISION: &str = crate_git_revision::git_revision!();
+/// pub const MY_GIT_REVISION: &str = crate_git_revision::git_revision!("MY_GIT_REVISION");
+/// ```
+///
+/// Without the build script calling [`init`][crate::init]:
+///
+/// ```compile_fail
+/// pub const GIT_REVISION: &str = crate_git_revision::git_revision!();
+/// ```
+#[macro_export]
+macro_rules! git_revision {
+    () => {
+        $crate::git_revision!("GIT_REVISION")
+    };
+    ($env_name:literal) => {
+        ::core::env!(
+            $env_name,
+            ::core::concat!(
+                "environment variable `",
+                $env_name,
+                "` is not set by the build script, add `crate-git-revision` to ",
+                "`[build-dependencies]` in Cargo.toml and call ",
+                "`crate_git_revision::init();` in build.rs"
+            )
+        )
+    };
+}
+
+/// Expands to a [`BuildInfo`][crate::BuildInfo] containing the git revision
+/// and metadata embedded by [`Builder`][crate::Builder].
+///
+/// Metadata not enabled in the builder is `None`. Takes the name of the
+/// environment variable of the revision if it was changed with
+/// [`Builder::env_name`][crate::Builder::env_name], and fails to compile like
+/// [`git_revision!`] if it is not set.
+///
+/// ### Examples
+///
+/// ```ignore
+/// pub const BUILD_INFO: crate_git_revision::BuildInfo = crate_git_revision::build_info!();
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build_info!("GIT_REVISION")
+    };
+    ($env_name:literal) => {
+        $crate::BuildInfo::__new(
+            $crate::git_revision!($env_name),
+            ::core::option_env!("GIT_BRANCH"),
+            ::core::option_env!("GIT_TAG"),
+            ::core::option_env!("GIT_DESCRIBE"),
+            ::core::option_env!("GIT_COMMIT_TIMESTAMP"),
+            ::core::option_env!("GIT_COMMIT_TIMESTAMP_RFC3339"),
+            ::core::option_env!("GIT_COMMIT_AUTHOR_DATE"),
+            ::core::option_env!("GIT_PATH_IN_VCS"),
+            ::core::option_env!("GIT_CRATE_REVISION"),
+            ::core::option_env!("GIT_CRATE_TREE"),
+            ::core::option_env!("GIT_REVISION_SOURCE"),
+            ::core::option_env!("GIT_OBJECT_FORMAT"),
+        )
+    };
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use crate::{ObjectFormat, SourceKind};
+
+/// The git revision of a crate, as discovered by [`try_init`][crate::try_init]
+/// or [`Builder::try_emit`][crate::Builder::try_emit].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Revision {
+    /// The revision, usually the full hex encoded commit hash. Revisions
+    /// without a commit are other identifiers, such as the content hash
+    /// `unknown+src.<hash>`, or any value of a custom
+    /// [`RevisionSource`][crate::RevisionSource].
+    pub sha: String,
+    /// Whether the working directory had changes not in the commit.
+    pub dirty: bool,
+    /// Name of the branch checked out, if not detached.
+    pub branch: Option<String>,
+    /// Name of a tag pointing at the commit.
+    pub tag: Option<String>,
+    /// Output of `git describe --tags --always` for the commit.
+    pub describe: Option<String>,
+    /// Committer date of the commit, in seconds since the unix epoch.
+    pub commit_timestamp: Option<i64>,
+    /// Committer date of the commit, in RFC 3339 format.
+    pub commit_date: Option<String>,
+    /// Author date of the commit, in RFC 3339 format.
+    pub author_date: Option<String>,
+    /// Path of the crate relative to the root of the repository, for
+    /// published crates.
+    pub path_in_vcs: Option<String>,
+    /// Full hex encoded hash of the last commit that changed the crate's
+    /// directory.
+    pub crate_revision: Option<String>,
+    /// Full hex encoded hash of the tree of the crate's directory in the
+    /// commit.
+    pub crate_tree: Option<String>,
+    /// Where the crate being built comes from.
+    pub source_kind: Option<SourceKind>,
+    /// The object format of the repository, which determines the length of
+    /// the commit hash.
+    pub object_format: Option<ObjectFormat>,
+}
+
+impl Revision {
+    /// Number of characters in the short form of the commit hash.
+    pub const SHORT_LEN: usize = 7;
+
+    /// Create a revision from a full hex encoded commit hash, without any
+    /// metadata, for use in a [`RevisionSource`][crate::RevisionSource].
+    pub fn new(sha: impl Into<String>, dirty: bool) -> Self {
+        Self {
+            sha: sha.into(),
+            dirty,
+            branch: None,
+            tag: None,
+            describe: None,
+            commit_timestamp: None,
+            commit_date: None,
+            author_date: None,
+            path_in_vcs: None,
+            crate_revision: None,
+            crate_tree: None,
+            source_kind: None,
+            object_format: None,
+        }
+    }
+
+    /// The short form of the commit hash.
+    pub fn short(&self) -> &str {
+        self.sha.get(..Self::SHORT_LEN).unwrap_or(&self.sha)
+    }
+
+    /// The revision with the suffix appended if the revision is dirty.
+    pub fn to_string_with_suffix(&self, dirty_suffix: &str) -> String {
+        if self.dirty {
+            format!("{}{dirty_suffix}", self.sha)
+        } else {
+            self.sha.clone()
+        }
+    }
+
+    /// The describe output with the suffix appended if the revision is dirty.
+    pub fn describe_with_suffix(&self, dirty_suffix: &str) -> Option<String> {
+        let suffix = if self.dirty { dirty_suffix } else { "" };
+        self.describe
+            .as_ref()
+            .map(|describe| format!("{describe}{suffix}"))
+    }
+}
+
+/// Metadata about the commit to look up in addition to its hash. Looking up
+/// metadata may require running additional git commands, so only the metadata
+/// requested is looked up.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metadata {
+    /// Paths relative to the crate's directory that changes are considered in
+    /// when determining whether the working directory is dirty, or `None` for
+    /// the whole repository.
+    pub(crate) dirty_paths: Option<Vec<PathBuf>>,
+    pub(crate) branch: bool,
+    pub(crate) tag: bool,
+    pub(crate) describe: bool,
+    pub(crate) commit_timestamp: bool,
+    pub(crate) author_date: bool,
+    pub(crate) crate_revision: bool,
+    pub(crate) crate_tree: bool,
+}
@@ -0,0 +1,97 @@
+use std::{fs, io, path::Path};
+
+use crate::{ObjectFormat, Revision, SourceKind};
+
+/// Name of the Rust source file written to `OUT_DIR`.
+pub(crate) const SOURCE_FILE_NAME: &str = "git_revision.rs";
+
+/// Name of the file containing only the revision written to `OUT_DIR`.
+pub(crate) const REVISION_FILE_NAME: &str = "git_revision.txt";
+
+/// Write the Rust source file containing constants for the revision into the
+/// directory.
+pub(crate) fn write(out_dir: &Path, revision: &Revision, dirty_suffix: &str) -> io::Result<()> {
+    write_if_changed(
+        &out_dir.join(SOURCE_FILE_NAME),
+        &generate(revision, dirty_suffix),
+    )?;
+    Ok(())
+}
+
+/// Write the file containing the revision into the directory.
+pub(crate) fn write_revision(
+    out_dir: &Path,
+    revision: &Revision,
+    dirty_suffix: &str,
+) -> io::Result<()> {
+    write_if_changed(
+        &out_dir.join(REVISION_FILE_NAME),
+        &revision.to_string_with_suffix(dirty_suffix),
+    )?;
+    Ok(())
+}
+
+/// Write the file only if its contents are different, so that its
+/// modification time only changes when its contents change. Returns whether
+/// the file was written.
+pub(crate) fn write_if_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == contents => Ok(false),
+        _ => fs::write(path, contents).map(|()| true),
+    }
+}
+
+/// Generate Rust source containing constants for the revision.
+pub(crate) fn generate(revision: &Revision, dirty_suffix: &str) -> String {
+    format!(
+        "\
+// Generated by crate-git-revision. Do not edit.
+
+/// The git revision, with the dirty suffix if the working directory was dirty.
+pub const REVISION: &str = {revision:?};
+/// Full hex encoded commit hash.
+pub const SHA: &str = {sha:?};
+/// Short form of the commit hash.
+pub const SHORT: &str = {short:?};
+/// Whether the working directory had changes not in the commit.
+pub const DIRTY: bool = {dirty:?};
+/// Name of the branch checked out.
+pub const BRANCH: Option<&str> = {branch:?};
+/// Name of a tag pointing at the commit.
+pub const TAG: Option<&str> = {tag:?};
+/// Output of `git describe --tags --always` for the commit.
+pub const DESCRIBE: Option<&str> = {describe:?};
+/// Committer date of the commit, in seconds since the unix epoch.
+pub const COMMIT_TIMESTAMP: Option<i64> = {commit_timestamp:?};
+/// Committer date of the commit, in RFC 3339 format.
+pub const COMMIT_DATE: Option<&str> = {commit_date:?};
+/// Author date of the commit, in RFC 3339 format.
+pub const AUTHOR_DATE: Option<&str> = {author_date:?};
+/// Path of the crate relative to the root of the repository.
+pub const PATH_IN_VCS: Option<&str> = {path_in_vcs:?};
+/// Hash of the last commit that changed the crate's directory.
+pub const CRATE_REVISION: Option<&str> = {crate_revision:?};
+/// Hash of the tree of the crate's directory in the commit.
+pub const CRATE_TREE: Option<&str> = {crate_tree:?};
+/// Where the crate was built from: `registry`, `git`, `path` or `workspace`.
+pub const REVISION_SOURCE: Option<&str> = {revision_source:?};
+/// Object format of the repository: `sha1` or `sha256`.
+pub const OBJECT_FORMAT: Option<&str> = {object_format:?};
+",
+        revision = revision.to_string_with_suffix(dirty_suffix),
+        sha = revision.sha,
+        short = revision.short(),
+        dirty = revision.dirty,
+        branch = revision.branch,
+        tag = revision.tag,
+        describe = revision.describe_with_suffix(dirty_suffix),
+        commit_timestamp = revision.commit_timestamp,
+        commit_date = revision.commit_date,
+        author_date = revision.author_date,
+        path_in_vcs = revision.path_in_vcs,
+        crate_revision = revision.crate_revision,
+        crate_tree = revision.crate_tree,
+        revision_source = revision.source_kind.map(SourceKind::as_str),
+        object_format = revision.object_format.map(ObjectFormat::as_str),
+    )
+}
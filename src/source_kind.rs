@@ -0,0 +1,88 @@
+use std::{fmt, path::Path};
+
+/// Where the crate being built comes from, as emitted in
+/// `GIT_REVISION_SOURCE` by [`Builder::emit_revision_source`][crate::Builder::emit_revision_source].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SourceKind {
+    /// A package downloaded from a registry, or vendored from one, whose
+    /// revision is the one it was published from. Emitted as `registry`.
+    Registry,
+    /// A git dependency, or a crate installed with `cargo install --git`,
+    /// checked out by cargo. Emitted as `git`.
+    Git,
+    /// A path dependency of a project outside the crate's workspace. Emitted
+    /// as `path`.
+    Path,
+    /// A crate in the workspace being built. Emitted as `workspace`.
+    Workspace,
+}
+
+impl SourceKind {
+    /// The value emitted in `GIT_REVISION_SOURCE`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::Registry => "registry",
+            SourceKind::Git => "git",
+            SourceKind::Path => "path",
+            SourceKind::Workspace => "workspace",
+        }
+    }
+
+    /// Classify the crate in the directory from its location, and the
+    /// location of the directory cargo is building it in, if known.
+    pub(crate) fn classify(manifest_dir: &Path, out_dir: Option<&Path>) -> Self {
+        // Cargo checks out git dependencies to
+        // `$CARGO_HOME/git/checkouts/<name>-<hash>/<rev>`, and extracts
+        // packages to `$CARGO_HOME/registry/src/<registry>/<name>-<version>`.
+        // Packages published from git, including vendored copies, contain
+        // `.cargo_vcs_info.json`, and `cargo vendor` adds
+        // `.cargo-checksum.json` to all packages it vendors.
+        if manifest_dir
+            .ancestors()
+            .any(|dir| dir.ends_with("git/checkouts"))
+        {
+            return SourceKind::Git;
+        }
+        if manifest_dir
+            .ancestors()
+            .any(|dir| dir.ends_with("registry/src"))
+            || manifest_dir.join(".cargo_vcs_info.json").is_file()
+            || manifest_dir.join(".cargo-checksum.json").is_file()
+        {
+            return SourceKind::Registry;
+        }
+
+        // The target directory, which contains `OUT_DIR`, defaults to `target`
+        // in the root of the workspace being built, so it is only within the
+        // crate's workspace when the workspace is being built.
+        match out_dir {
+            Some(out_dir) if out_dir.starts_with(workspace_root(manifest_dir)) => {
+                SourceKind::Workspace
+            }
+            _ => SourceKind::Path,
+        }
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The outermost directory containing a `Cargo.toml` from the directory up to
+/// the root of the repository containing it, which is the root of the
+/// workspace if the crate is in one.
+fn workspace_root(manifest_dir: &Path) -> &Path {
+    let mut root = manifest_dir;
+    for dir in manifest_dir.ancestors() {
+        if dir.join("Cargo.toml").is_file() {
+            root = dir;
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    root
+}
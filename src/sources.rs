@@ -0,0 +1,533 @@
+//! Sources of the git revision of a crate, tried in order by [`Builder`]
+//! until one has a revision.
+//!
+//! The default chain of sources is [`VcsInfo`], [`Git`], [`EnvOverride`] and
+//! [`GitArchival`], followed by [`CiEnv`] if [`Builder::ci_revision`] is
+//! enabled, and `ContentHash` if `Builder::content_hash_fallback` is enabled.
+//! Use [`Builder::sources`] to replace the chain, for example to read the
+//! revision from a file of a custom format:
+//!
+//! ```no_run
+//! use crate_git_revision::{sources, Error, Revision, RevisionSource, SourceContext};
+//!
+//! #[derive(Debug)]
+//! struct RevisionFile;
+//!
+//! impl RevisionSource for RevisionFile {
+//!     fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+//!         let path = context.manifest_dir().join("REVISION");
+//!         match std::fs::read_to_string(&path) {
+//!             Ok(sha) => {
+//!                 context.rerun_if_changed(path);
+//!                 Ok(Some(Revision::new(sha.trim(), false)))
+//!             }
+//!             Err(_) => Ok(None),
+//!         }
+//!     }
+//! }
+//!
+//! crate_git_revision::Builder::new()
+//!     .sources(vec![
+//!         Box::new(sources::VcsInfo),
+//!         Box::new(sources::Git),
+//!         Box::new(RevisionFile),
+//!     ])
+//!     .emit();
+//! ```
+
+use std::{
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "content-hash")]
+use crate::content_hash;
+#[cfg(feature = "git2")]
+use crate::git2_backend;
+#[cfg(feature = "gix")]
+use crate::gix_backend;
+use crate::{
+    archival, command, env, files, revision::Metadata, vendored, Backend, Builder, CargoVcsInfo,
+    Error, ObjectFormat, RequireClean, Revision,
+};
+
+/// A source of the git revision of a crate.
+///
+/// Sources return `None` when they have no revision for the crate, in which
+/// case the next source in the chain is tried, and an error when they have a
+/// revision that cannot be read, which stops the chain.
+pub trait RevisionSource: fmt::Debug + Send + Sync {
+    /// Get the revision of the crate, recording in the context what to rerun
+    /// the build script on if the revision could change.
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error>;
+}
+
+/// The crate a [`RevisionSource`] gets the revision of.
+///
+/// Rerun directives recorded by sources are emitted to cargo once a revision
+/// is found, including those of sources earlier in the chain that had no
+/// revision.
+pub struct SourceContext<'a> {
+    pub(crate) builder: &'a Builder,
+    manifest_dir: &'a Path,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    pub(crate) rerun_if_changed: Vec<PathBuf>,
+    pub(crate) rerun_if_env_changed: Vec<String>,
+    pub(crate) warnings: Vec<String>,
+}
+
+impl<'a> SourceContext<'a> {
+    pub(crate) fn new(
+        builder: &'a Builder,
+        manifest_dir: &'a Path,
+        env: &'a dyn Fn(&str) -> Option<String>,
+    ) -> Self {
+        Self {
+            builder,
+            manifest_dir,
+            env,
+            rerun_if_changed: Vec::new(),
+            rerun_if_env_changed: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Directory of the crate.
+    pub fn manifest_dir(&self) -> &'a Path {
+        self.manifest_dir
+    }
+
+    /// Get the value of an environment variable, and rerun the build script
+    /// when it changes.
+    pub fn var(&mut self, name: &str) -> Option<String> {
+        self.rerun_if_env_changed(name);
+        (self.env)(name)
+    }
+
+    /// Rerun the build script when the file changes. Relative paths are
+    /// relative to the crate's directory.
+    pub fn rerun_if_changed(&mut self, path: impl Into<PathBuf>) {
+        self.rerun_if_changed.push(path.into());
+    }
+
+    /// Rerun the build script when the environment variable changes.
+    pub fn rerun_if_env_changed(&mut self, name: &str) {
+        if !self.rerun_if_env_changed.iter().any(|n| n == name) {
+            self.rerun_if_env_changed.push(name.to_string());
+        }
+    }
+
+    /// Report a warning to cargo, such as why a source has no revision. Unlike
+    /// rerun directives, warnings are emitted whether or not a revision is
+    /// found.
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.warnings.push(message.into());
+    }
+
+    /// Whether a dirty working directory fails the build, as configured with
+    /// `CRATE_GIT_REVISION_REQUIRE_CLEAN`, or otherwise with
+    /// [`Builder::require_clean`]. Only called for dirty revisions, or when
+    /// changes are not detected, as the variable cannot change the outcome for
+    /// clean revisions.
+    pub(crate) fn require_clean(&mut self) -> bool {
+        match self.var(env::REQUIRE_CLEAN_ENV).as_deref() {
+            Some("1") => return true,
+            Some("0") => return false,
+            _ => {}
+        }
+        match self.builder.require_clean {
+            RequireClean::Never => false,
+            // Cargo sets the profile for build scripts, and reruns them when
+            // it changes, so it is not watched.
+            RequireClean::Release => (self.env)("PROFILE").as_deref() == Some("release"),
+            RequireClean::Always => true,
+        }
+    }
+}
+
+/// The `.cargo_vcs_info.json` file cargo embeds in published crates.
+#[derive(Clone, Copy, Debug)]
+pub struct VcsInfo;
+
+impl RevisionSource for VcsInfo {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        let vcs_info = match read_to_string(context.manifest_dir().join(".cargo_vcs_info.json")) {
+            Ok(vcs_info) => vcs_info,
+            Err(_) => return Ok(None),
+        };
+        let vcs_info: CargoVcsInfo =
+            serde_json::from_str(&vcs_info).map_err(Error::InvalidVcsInfo)?;
+        let mut revision = Revision::new(vcs_info.git.sha1, vcs_info.git.dirty);
+        revision.path_in_vcs = vcs_info.path_in_vcs;
+        Ok(Some(revision))
+    }
+}
+
+/// The `CRATE_GIT_REVISION` environment variable, containing a full commit
+/// hash optionally followed by `-dirty`.
+///
+/// In the default chain it is read after [`Git`], so it only sets the
+/// revision of crates not in a git repository, or in one that could not be
+/// opened, such as when `git` refuses a repository owned by another user.
+#[derive(Clone, Copy, Debug)]
+pub struct EnvOverride;
+
+impl RevisionSource for EnvOverride {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        env::revision(env::REVISION_ENV, context.var(env::REVISION_ENV))
+    }
+}
+
+/// The git repository containing the crate, read with the
+/// [`Backend`] and options configured in the [`Builder`].
+#[derive(Clone, Copy, Debug)]
+pub struct Git;
+
+impl RevisionSource for Git {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        let builder = context.builder;
+        let current_dir = context.manifest_dir();
+        let repository = match Repository::discover(builder.backend, current_dir) {
+            Ok(repository) => repository,
+            Err(Error::NotARepository) => return Ok(None),
+            // Such as git refusing a repository owned by another user, which
+            // leaves the revision to the sources after this one.
+            Err(e @ Error::GitFailed { .. }) => return Ok(unreadable(context, &e)),
+            #[cfg(feature = "gix")]
+            Err(e @ Error::Gix(_)) => return Ok(unreadable(context, &e)),
+            #[cfg(feature = "git2")]
+            Err(e @ Error::Git2(_)) => return Ok(unreadable(context, &e)),
+            Err(e) => return Err(e),
+        };
+        let git_dir = repository.git_dir();
+        let common_dir = repository.common_dir();
+
+        // Require the build script to rerun if relavent git state changes which
+        // changes the current git commit. In linked worktrees the git dir is
+        // .git/worktrees/<name>, and the refs are in the common dir shared by
+        // all worktrees, which is .git.
+        //  - .git/index: Changes if the index/staged files changes, which will
+        //  cause the repo to be dirty. Not watched with the revision file,
+        //  which watches the crate's tracked files instead.
+        //  - .git/HEAD: Changes if the ref currently in the working directory,
+        //  and potentially the commit, to change.
+        //  - .git/refs/heads/<branch>: Changes if the commit of the branch in
+        //  .git/HEAD changes. Other refs are not watched, so fetching or
+        //  tagging does not rerun the build script.
+        //  - .git/packed-refs: Changes if refs are packed, or the ref in
+        //  .git/HEAD is packed and changed.
+        // Note: That changes in the above files may not result in material
+        // changes to the crate, but changes in any should invalidate the
+        // revision since the revision can be changed by any of the above.
+        let head_files = files::Repository {
+            git_dir: git_dir.to_path_buf(),
+            common_dir: common_dir.to_path_buf(),
+        }
+        .head_files(current_dir)?;
+        if !builder.revision_file {
+            context.rerun_if_changed(git_dir.join("index"));
+        }
+        for file in head_files {
+            context.rerun_if_changed(file);
+        }
+
+        if let Some(reason) = vendored(&repository, context)? {
+            context.warning(format!(
+                "Ignoring the git repository containing the crate, {reason}"
+            ));
+            return Ok(None);
+        }
+
+        if builder.watch_tracked_files || builder.revision_file {
+            // Files deleted from the working directory but not from the index
+            // are left out, cargo would otherwise rerun the build every time.
+            for file in repository.tracked_files(current_dir)? {
+                if current_dir.join(&file).exists() {
+                    context.rerun_if_changed(file);
+                }
+            }
+        }
+
+        let mut metadata = builder.metadata.clone();
+        if builder.dirty_crate_only {
+            let mut paths = vec![PathBuf::from(".")];
+            paths.extend(builder.dirty_paths.iter().cloned());
+            metadata.dirty_paths = Some(paths);
+        }
+        let mut revision = repository.revision(&metadata)?;
+        let object_format = repository.object_format()?;
+        let hashes = [
+            Some(&revision.sha),
+            revision.crate_revision.as_ref(),
+            revision.crate_tree.as_ref(),
+        ];
+        for sha in hashes.into_iter().flatten() {
+            if !object_format.is_hex_sha(sha) {
+                return Err(Error::InvalidSha {
+                    sha: sha.clone(),
+                    object_format,
+                });
+            }
+        }
+        revision.object_format = Some(object_format);
+        // Changes are not detected without the git executable, so a clean
+        // working directory cannot be verified.
+        if let Repository::Files(_) = repository {
+            if context.require_clean() {
+                return Err(Error::CleanUnverified);
+            }
+        } else if revision.dirty && context.require_clean() {
+            return Err(Error::Dirty {
+                paths: repository.modified_paths(&metadata)?,
+            });
+        }
+        Ok(Some(revision))
+    }
+}
+
+/// The error opening the repository with a library, or a specific error if
+/// the repository uses an object format other than SHA-1, which the libraries
+/// fail to open it with.
+#[cfg(any(feature = "gix", feature = "git2"))]
+fn unsupported_object_format(backend: Backend, current_dir: &Path, e: Error) -> Error {
+    match files::Repository::discover(current_dir).and_then(|r| r.object_format()) {
+        Ok(object_format) if object_format != ObjectFormat::Sha1 => {
+            Error::UnsupportedObjectFormat {
+                backend,
+                object_format,
+            }
+        }
+        _ => e,
+    }
+}
+
+/// Warn that the repository containing the crate could not be opened, and
+/// return no revision.
+fn unreadable(context: &mut SourceContext<'_>, e: &Error) -> Option<Revision> {
+    context.warning(format!(
+        "Ignoring the git repository containing the crate, as it could not be opened: {e}"
+    ));
+    None
+}
+
+/// Why the crate appears to have been vendored or copied into the repository
+/// containing it, in which case the revision of the repository is not the
+/// revision of the crate, or `None` if it does not.
+fn vendored(repository: &Repository, context: &SourceContext<'_>) -> Result<Option<String>, Error> {
+    let builder = context.builder;
+    let current_dir = context.manifest_dir();
+    if builder.detect_untracked
+        && current_dir.join("Cargo.toml").is_file()
+        && repository.is_tracked(current_dir, "Cargo.toml")? == Some(false)
+    {
+        return Ok(Some(
+            "as the crate's Cargo.toml is not tracked in it".to_string(),
+        ));
+    }
+    if !builder.detect_vendored {
+        return Ok(None);
+    }
+
+    // Cargo sets the repository from the manifest, which reruns the build
+    // script when it changes, so it is not watched.
+    let url = match (context.env)("CARGO_PKG_REPOSITORY") {
+        Some(url) if !url.is_empty() => url,
+        _ => return Ok(None),
+    };
+    let remotes = repository.remote_urls()?;
+    if !remotes.is_empty() && !vendored::matches_remotes(&url, &remotes) {
+        return Ok(Some(format!(
+            "as the crate's repository {url} does not match its remotes"
+        )));
+    }
+    Ok(None)
+}
+
+/// The `.git_archival.txt` file git substitutes commit information into when
+/// creating source archives, in the crate's directory or its parents.
+#[derive(Clone, Copy, Debug)]
+pub struct GitArchival;
+
+impl RevisionSource for GitArchival {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        let (path, contents) = match archival::find(context.manifest_dir())? {
+            Some(archival) => archival,
+            None => return Ok(None),
+        };
+        let revision = archival::parse(&contents, &context.builder.metadata);
+        if revision.is_some() {
+            context.rerun_if_changed(path);
+        }
+        Ok(revision)
+    }
+}
+
+/// The environment variables CI services set to the commit being built,
+/// `GITHUB_SHA`, `CI_COMMIT_SHA`, `BUILDKITE_COMMIT`, `CIRCLE_SHA1`,
+/// `TRAVIS_COMMIT` and `BITBUCKET_COMMIT`, in that order.
+#[derive(Clone, Copy, Debug)]
+pub struct CiEnv;
+
+impl RevisionSource for CiEnv {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        for name in env::CI_ENVS {
+            // Some CI services set the variables to values other than commit
+            // hashes in some cases, which are skipped.
+            if let Ok(Some(revision)) = env::revision(name, context.var(name)) {
+                return Ok(Some(revision));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A hash of the crate's package files, `unknown+src.<hash>`, which always
+/// has a revision. Requires the `content-hash` feature.
+#[cfg(feature = "content-hash")]
+#[derive(Clone, Copy, Debug)]
+pub struct ContentHash;
+
+#[cfg(feature = "content-hash")]
+impl RevisionSource for ContentHash {
+    fn revision(&self, context: &mut SourceContext<'_>) -> Result<Option<Revision>, Error> {
+        let current_dir = context.manifest_dir();
+        let files = content_hash::package_files(current_dir)?;
+        let hash = content_hash::hash(current_dir, &files)?;
+        for file in files {
+            context.rerun_if_changed(file);
+        }
+        Ok(Some(Revision::new(
+            format!("{}{hash}", content_hash::PREFIX),
+            false,
+        )))
+    }
+}
+
+/// A git repository read by one of the backends.
+enum Repository {
+    Command(command::Repository),
+    Files(files::Repository),
+    #[cfg(feature = "gix")]
+    Gix(Box<gix_backend::Repository>),
+    #[cfg(feature = "git2")]
+    Git2(git2_backend::Repository),
+}
+
+impl Repository {
+    fn discover(backend: Backend, current_dir: &Path) -> Result<Self, Error> {
+        Ok(match backend {
+            Backend::Auto => match command::Repository::discover(current_dir) {
+                Err(Error::GitNotFound) => {
+                    Repository::Files(files::Repository::discover(current_dir)?)
+                }
+                repository => Repository::Command(repository?),
+            },
+            Backend::Command => Repository::Command(command::Repository::discover(current_dir)?),
+            Backend::Files => Repository::Files(files::Repository::discover(current_dir)?),
+            #[cfg(feature = "gix")]
+            Backend::Gix => Repository::Gix(Box::new(
+                gix_backend::Repository::discover(current_dir)
+                    .map_err(|e| unsupported_object_format(backend, current_dir, e))?,
+            )),
+            #[cfg(feature = "git2")]
+            Backend::Git2 => Repository::Git2(
+                git2_backend::Repository::discover(current_dir)
+                    .map_err(|e| unsupported_object_format(backend, current_dir, e))?,
+            ),
+        })
+    }
+
+    fn git_dir(&self) -> &Path {
+        match self {
+            Repository::Command(repository) => &repository.git_dir,
+            Repository::Files(repository) => &repository.git_dir,
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => &repository.git_dir,
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => &repository.git_dir,
+        }
+    }
+
+    fn common_dir(&self) -> &Path {
+        match self {
+            Repository::Command(repository) => &repository.common_dir,
+            Repository::Files(repository) => &repository.common_dir,
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => &repository.common_dir,
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => &repository.common_dir,
+        }
+    }
+
+    fn tracked_files(&self, current_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        match self {
+            Repository::Command(repository) => repository.tracked_files(current_dir),
+            // The index is not read without the git executable.
+            Repository::Files(_) => Ok(Vec::new()),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => repository.tracked_files(current_dir),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.tracked_files(current_dir),
+        }
+    }
+
+    /// Whether the file, relative to the directory, is tracked in the index, or
+    /// `None` if the index is not read.
+    fn is_tracked(&self, current_dir: &Path, file: &str) -> Result<Option<bool>, Error> {
+        match self {
+            Repository::Command(repository) => repository.is_tracked(current_dir, file).map(Some),
+            Repository::Files(_) => Ok(None),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => repository.is_tracked(current_dir, file).map(Some),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.is_tracked(current_dir, file).map(Some),
+        }
+    }
+
+    fn object_format(&self) -> Result<ObjectFormat, Error> {
+        match self {
+            Repository::Command(repository) => Ok(repository.object_format),
+            Repository::Files(repository) => repository.object_format(),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => repository.object_format(),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.object_format(),
+        }
+    }
+
+    fn remote_urls(&self) -> Result<Vec<String>, Error> {
+        match self {
+            Repository::Command(repository) => repository.remote_urls(),
+            // The config is not read without the git executable.
+            Repository::Files(_) => Ok(Vec::new()),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => Ok(repository.remote_urls()),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.remote_urls(),
+        }
+    }
+
+    fn modified_paths(&self, metadata: &Metadata) -> Result<Vec<PathBuf>, Error> {
+        match self {
+            Repository::Command(repository) => repository.modified_paths(metadata),
+            // The working directory is never dirty without the git executable.
+            Repository::Files(_) => Ok(Vec::new()),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => repository.modified_paths(metadata),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.modified_paths(metadata),
+        }
+    }
+
+    fn revision(&self, metadata: &Metadata) -> Result<Revision, Error> {
+        match self {
+            Repository::Command(repository) => repository.revision(metadata),
+            Repository::Files(repository) => repository.revision(metadata),
+            #[cfg(feature = "gix")]
+            Repository::Gix(repository) => repository.revision(metadata),
+            #[cfg(feature = "git2")]
+            Repository::Git2(repository) => repository.revision(metadata),
+        }
+    }
+}
@@ -1,11 +1,51 @@
 #![cfg(test)]
 
+use super::GitRevision;
 use regex::Regex;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str;
 
+/// An environment without any variables set, so that tests do not depend on
+/// the environment they are run in.
+fn no_env(_: &str) -> Option<String> {
+    None
+}
+
+/// Read environment variables from the pairs of names and values.
+fn vars_env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    move |name| {
+        vars.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+/// Emit with the builder, reading environment variables from the pairs of
+/// names and values, returning the result and the output.
+fn emit_with_env(
+    builder: super::Builder,
+    vars: &[(&str, &str)],
+) -> (Result<super::Revision, super::Error>, String) {
+    let env = vars_env(vars);
+    let mut out = Vec::new();
+    let res = builder.try_emit_to_with_env(&mut out, &env);
+    (res, String::from_utf8(out).unwrap())
+}
+
+/// Emit with the builder like [`emit_with_env`], handling errors with the
+/// fallback.
+fn emit_fallback_with_env(
+    builder: super::Builder,
+    vars: &[(&str, &str)],
+) -> (Result<(), super::Error>, String) {
+    let env = vars_env(vars);
+    let mut out = Vec::new();
+    let res = builder.emit_to_with_env(&mut out, &env);
+    (res, String::from_utf8(out).unwrap())
+}
+
 fn init_git_repo(path: &Path) {
     let output = Command::new("git")
         .current_dir(path)
@@ -66,8 +106,8 @@ fn test_init() {
     let out = str::from_utf8(&out).unwrap();
     let expected = "cargo:rerun-if-changed=.git/index
 cargo:rerun-if-changed=.git/HEAD
-cargo:rerun-if-changed=.git/refs
-cargo:rustc-env=GIT_REVISION=[0-9a-f]+";
+cargo:rerun-if-changed=.git/refs/heads/[a-z]+
+cargo:rustc-env=GIT_REVISION=[0-9a-f]{40}";
     println!("{out}");
     println!("{expected}");
     assert!(Regex::new(expected).unwrap().is_match(out));
@@ -96,8 +136,8 @@ fn test_init_subdir() {
     let expected = &format!(
         "cargo:rerun-if-changed={gd}/.git/index
 cargo:rerun-if-changed={gd}/.git/HEAD
-cargo:rerun-if-changed={gd}/.git/refs
-cargo:rustc-env=GIT_REVISION=[0-9a-f]+",
+cargo:rerun-if-changed={gd}/.git/refs/heads/[a-z]+
+cargo:rustc-env=GIT_REVISION=[0-9a-f]{{40}}",
         gd = git_dir.display()
     );
     println!("{out}");
@@ -121,8 +161,9 @@ fn test_dirty() {
     let out = str::from_utf8(&out).unwrap();
     let expected = "cargo:rerun-if-changed=.git/index
 cargo:rerun-if-changed=.git/HEAD
-cargo:rerun-if-changed=.git/refs
-cargo:rustc-env=GIT_REVISION=[0-9a-f]+-dirty";
+cargo:rerun-if-changed=.git/refs/heads/[a-z]+
+cargo:rerun-if-env-changed=CRATE_GIT_REVISION_REQUIRE_CLEAN
+cargo:rustc-env=GIT_REVISION=[0-9a-f]{40}-dirty";
     println!("{out}");
     println!("{expected}");
     assert!(Regex::new(expected).unwrap().is_match(out));
@@ -143,6 +184,61 @@ fn test_published() {
     let file = crate_dir.join(".cargo_vcs_info.json");
     fs::write(file, vcs_info).unwrap();
 
+    let mut out = Vec::new();
+    let res = super::__init(&mut out, crate_dir);
+    assert!(res.is_ok());
+    let out = str::from_utf8(&out).unwrap();
+    let expected = "cargo:rustc-env=GIT_REVISION=0c5255b6f47649305fcb68edccb285510aec71a7
+cargo:rustc-env=GIT_PATH_IN_VCS=
+";
+    println!("{out}");
+    println!("{expected}");
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_published_dirty() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    let vcs_info = r#"{
+  "git": {
+    "sha1": "0c5255b6f47649305fcb68edccb285510aec71a7",
+    "dirty": true
+  },
+  "path_in_vcs": "crates/example"
+}"#;
+
+    let file = crate_dir.join(".cargo_vcs_info.json");
+    fs::write(file, vcs_info).unwrap();
+
+    let mut out = Vec::new();
+    let res = super::__init(&mut out, crate_dir);
+    assert!(res.is_ok());
+    let out = str::from_utf8(&out).unwrap();
+    let expected = "cargo:rustc-env=GIT_REVISION=0c5255b6f47649305fcb68edccb285510aec71a7-dirty
+cargo:rustc-env=GIT_PATH_IN_VCS=crates/example
+";
+    println!("{out}");
+    println!("{expected}");
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_published_without_path_in_vcs() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    // Crates published before cargo 1.58 do not contain path_in_vcs.
+    let vcs_info = r#"{
+  "git": {
+    "sha1": "0c5255b6f47649305fcb68edccb285510aec71a7"
+  }
+}"#;
+
+    let file = crate_dir.join(".cargo_vcs_info.json");
+    fs::write(file, vcs_info).unwrap();
+
     let mut out = Vec::new();
     let res = super::__init(&mut out, crate_dir);
     assert!(res.is_ok());
@@ -152,3 +248,2157 @@ fn test_published() {
     println!("{expected}");
     assert_eq!(out, expected);
 }
+
+#[test]
+fn test_builder() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+
+    let file = git_dir.join("readme");
+    fs::write(file, "dirty").unwrap();
+
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(git_dir)
+        .env_name("MY_REVISION")
+        .dirty_suffix("-modified")
+        .emit_to_with_env(&mut out, &no_env);
+    assert!(res.is_ok());
+    let out = str::from_utf8(&out).unwrap();
+    let expected = "cargo:rerun-if-changed=.git/index
+cargo:rerun-if-changed=.git/HEAD
+cargo:rerun-if-changed=.git/refs/heads/[a-z]+
+cargo:rerun-if-env-changed=CRATE_GIT_REVISION_REQUIRE_CLEAN
+cargo:rustc-env=MY_REVISION=[0-9a-f]{40}-modified";
+    println!("{out}");
+    println!("{expected}");
+    assert!(Regex::new(expected).unwrap().is_match(out));
+}
+
+#[test]
+fn test_try_init() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+
+    let file = git_dir.join("readme");
+    fs::write(file, "dirty").unwrap();
+
+    let mut out = Vec::new();
+    let revision = super::Builder::new()
+        .manifest_dir(git_dir)
+        .try_emit_to_with_env(&mut out, &no_env)
+        .unwrap();
+    assert!(Regex::new("^[0-9a-f]{40}$")
+        .unwrap()
+        .is_match(&revision.sha));
+    assert!(revision.dirty);
+    let out = str::from_utf8(&out).unwrap();
+    assert!(out.ends_with(&format!(
+        "cargo:rustc-env=GIT_REVISION={}-dirty\n",
+        revision.sha
+    )));
+}
+
+#[test]
+fn test_try_init_not_a_repository() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(crate_dir)
+        .try_emit_to_with_env(&mut out, &no_env);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+    assert!(out.is_empty());
+
+    let mut out = Vec::new();
+    let res = super::__init(&mut out, crate_dir);
+    assert!(res.is_ok());
+    let out = str::from_utf8(&out).unwrap();
+    assert!(out.starts_with("cargo:warning=Error getting git revision: not a git repository"));
+}
+
+/// A crate installed from a vendored source tarball, which has neither a git
+/// repository nor a `.cargo_vcs_info.json` file.
+fn source_tarball() -> tempfile::TempDir {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+    fs::write(
+        crate_dir.join("Cargo.toml"),
+        "[package]\nname = \"vendored\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    fs::create_dir(crate_dir.join("src")).unwrap();
+    fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    tempdir
+}
+
+const FALLBACK_WARNING: &str = "cargo:warning=Error getting git revision: not a git repository";
+
+#[test]
+fn test_fallback() {
+    let crate_dir = source_tarball();
+
+    // By default the revision is a placeholder, so the crate still compiles.
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .emit_short(true);
+    let (res, out) = emit_fallback_with_env(builder, &[]);
+    res.unwrap();
+    assert!(out.starts_with(FALLBACK_WARNING));
+    assert!(out.ends_with("\ncargo:rustc-env=GIT_REVISION=unknown\n"));
+    // The build script reruns when a source may have a revision.
+    assert!(out
+        .lines()
+        .any(|line| line == "cargo:rerun-if-env-changed=CRATE_GIT_REVISION"));
+}
+
+#[test]
+fn test_fallback_placeholder() {
+    let crate_dir = source_tarball();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .env_name("MY_REVISION")
+        .fallback(super::Fallback::Placeholder("0.1.0".to_string()));
+    let (res, out) = emit_fallback_with_env(builder, &[]);
+    res.unwrap();
+    assert!(out.ends_with("\ncargo:rustc-env=MY_REVISION=0.1.0\n"));
+}
+
+#[test]
+fn test_fallback_placeholder_generated_files() {
+    let crate_dir = source_tarball();
+    let out_dir = tempfile::tempdir().unwrap();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .out_dir(out_dir.path())
+        .generate_source(true)
+        .revision_file(true);
+    let (res, _) = emit_fallback_with_env(builder, &[]);
+    res.unwrap();
+    let source = fs::read_to_string(out_dir.path().join("git_revision.rs")).unwrap();
+    assert!(source.contains("pub const REVISION: &str = \"unknown\";\n"));
+    assert!(source.contains("pub const DIRTY: bool = false;\n"));
+    let revision = fs::read_to_string(out_dir.path().join("git_revision.txt")).unwrap();
+    assert_eq!(revision, "unknown");
+}
+
+#[test]
+fn test_fallback_nothing() {
+    let crate_dir = source_tarball();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .fallback(super::Fallback::Nothing);
+    let (res, out) = emit_fallback_with_env(builder, &[]);
+    res.unwrap();
+    assert!(out.starts_with(FALLBACK_WARNING));
+    assert!(!out.contains("cargo:rustc-env"));
+    assert!(out
+        .lines()
+        .any(|line| line == "cargo:rerun-if-env-changed=CRATE_GIT_REVISION"));
+}
+
+#[test]
+fn test_fallback_error() {
+    let crate_dir = source_tarball();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .fallback(super::Fallback::Error);
+    let (res, out) = emit_fallback_with_env(builder, &[]);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+    assert!(!out.contains("cargo:rerun-if"));
+}
+
+#[test]
+fn test_fallback_not_used() {
+    let crate_dir = source_tarball();
+    let sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+    fs::write(
+        crate_dir.path().join(".cargo_vcs_info.json"),
+        format!(r#"{{"git":{{"sha1":"{sha}"}}}}"#),
+    )
+    .unwrap();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .fallback(super::Fallback::Error);
+    let (res, out) = emit_fallback_with_env(builder, &[]);
+    res.unwrap();
+    assert!(out.contains(&format!("cargo:rustc-env=GIT_REVISION={sha}\n")));
+}
+
+#[test]
+fn test_fallback_write_failed() {
+    let crate_dir = source_tarball();
+    let sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+    fs::write(
+        crate_dir.path().join(".cargo_vcs_info.json"),
+        format!(r#"{{"git":{{"sha1":"{sha}"}}}}"#),
+    )
+    .unwrap();
+
+    // The generated files cannot be written for the placeholder either, so
+    // the error is returned, and nothing is emitted for the revision.
+    let out_dir = crate_dir.path().join("Cargo.toml");
+    for builder in [
+        super::Builder::new().generate_source(true),
+        super::Builder::new().revision_file(true),
+    ] {
+        let builder = builder.manifest_dir(crate_dir.path()).out_dir(&out_dir);
+        let (res, out) = emit_fallback_with_env(builder, &[]);
+        assert!(matches!(res, Err(super::Error::Io(_))));
+        assert!(!out.contains("cargo:rustc-env"));
+    }
+}
+
+#[test]
+fn test_try_init_no_commits() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    let output = Command::new("git")
+        .current_dir(git_dir)
+        .arg("init")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(git_dir)
+        .try_emit_to_with_env(&mut out, &no_env);
+    assert!(matches!(res, Err(super::Error::GitFailed { .. })));
+}
+
+#[test]
+fn test_try_init_invalid_vcs_info() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    let file = crate_dir.join(".cargo_vcs_info.json");
+    fs::write(file, "{").unwrap();
+
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(crate_dir)
+        .try_emit_to_with_env(&mut out, &no_env);
+    assert!(matches!(res, Err(super::Error::InvalidVcsInfo(_))));
+    assert!(out.is_empty());
+}
+
+fn git(path: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(path)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn try_init_with_backend(
+    manifest_dir: &Path,
+    backend: super::Backend,
+) -> Result<super::Revision, super::Error> {
+    super::Builder::new()
+        .manifest_dir(manifest_dir)
+        .backend(backend)
+        .try_emit_to_with_env(&mut Vec::new(), &no_env)
+}
+
+#[test]
+fn test_files_backend() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+
+    let manifest_dir = git_dir.join("subdir");
+    std::fs::create_dir(&manifest_dir).unwrap();
+
+    let revision = try_init_with_backend(&manifest_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(!revision.dirty);
+
+    // Detached HEAD contains the commit hash directly.
+    git(git_dir, &["checkout", "--detach"]);
+    let revision = try_init_with_backend(&manifest_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.sha, head);
+}
+
+#[test]
+fn test_files_backend_packed_refs() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    git(git_dir, &["pack-refs", "--all"]);
+    assert!(git_dir.join(".git/packed-refs").is_file());
+
+    let revision = try_init_with_backend(git_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.sha, head);
+}
+
+#[test]
+fn test_files_backend_worktree() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path().join("main");
+    let worktree_dir = tempdir.path().join("worktree");
+    std::fs::create_dir(&git_dir).unwrap();
+
+    init_git_repo(&git_dir);
+    git(
+        &git_dir,
+        &[
+            "worktree",
+            "add",
+            "-b",
+            "other",
+            worktree_dir.to_str().unwrap(),
+        ],
+    );
+    fs::write(worktree_dir.join("readme"), "other").unwrap();
+    git(&worktree_dir, &["commit", "-am", "other"]);
+    let head = git(&worktree_dir, &["rev-parse", "HEAD"]);
+    assert_ne!(head, git(&git_dir, &["rev-parse", "HEAD"]));
+
+    let revision = try_init_with_backend(&worktree_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.sha, head);
+}
+
+#[test]
+fn test_files_backend_no_commits() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    git(git_dir, &["init"]);
+
+    let res = try_init_with_backend(git_dir, super::Backend::Files);
+    assert!(matches!(res, Err(super::Error::RefNotFound(_))));
+}
+
+#[cfg(any(feature = "gix", feature = "git2"))]
+fn assert_backend_matches_command(backend: super::Backend) {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+
+    let revision = try_init_with_backend(git_dir, backend).unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(!revision.dirty);
+
+    // Untracked files do not make the working directory dirty.
+    fs::write(git_dir.join("untracked"), "untracked").unwrap();
+    let revision = try_init_with_backend(git_dir, backend).unwrap();
+    assert!(!revision.dirty);
+
+    fs::write(git_dir.join("readme"), "dirty").unwrap();
+    let revision = try_init_with_backend(git_dir, backend).unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(revision.dirty);
+    assert_eq!(
+        revision,
+        try_init_with_backend(git_dir, super::Backend::Command).unwrap()
+    );
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let res = try_init_with_backend(tempdir.path(), backend);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+
+    // Metadata matches the git executable, including for annotated tags and
+    // dates in time zones other than UTC.
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    init_git_repo(git_dir);
+    git(git_dir, &["tag", "-a", "v1.0", "-m", "v1.0"]);
+    commit_with_dates(
+        git_dir,
+        "second",
+        "1999-12-31T20:17:40-05:00",
+        "2024-02-29T12:00:00+05:45",
+    );
+    let (revision, _) = try_init_with_metadata(git_dir, backend).unwrap();
+    let (expected, _) = try_init_with_metadata(git_dir, super::Backend::Command).unwrap();
+    assert_eq!(revision, expected);
+
+    git(git_dir, &["tag", "v1.1"]);
+    git(git_dir, &["checkout", "--detach"]);
+    let (revision, _) = try_init_with_metadata(git_dir, backend).unwrap();
+    let (expected, _) = try_init_with_metadata(git_dir, super::Backend::Command).unwrap();
+    assert_eq!(revision, expected);
+}
+
+#[cfg(feature = "gix")]
+#[test]
+fn test_gix_backend() {
+    assert_backend_matches_command(super::Backend::Gix);
+}
+
+#[cfg(feature = "git2")]
+#[test]
+fn test_git2_backend() {
+    assert_backend_matches_command(super::Backend::Git2);
+}
+
+fn commit_with_dates(path: &Path, message: &str, committer_date: &str, author_date: &str) {
+    let output = Command::new("git")
+        .current_dir(path)
+        .env("GIT_COMMITTER_DATE", committer_date)
+        .env("GIT_AUTHOR_DATE", author_date)
+        .args(["commit", "--allow-empty", "-m", message])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+fn try_init_with_metadata(
+    manifest_dir: &Path,
+    backend: super::Backend,
+) -> Result<(super::Revision, String), super::Error> {
+    let builder = super::Builder::new()
+        .manifest_dir(manifest_dir)
+        .backend(backend)
+        .emit_branch(true)
+        .emit_tag(true)
+        .emit_describe(true)
+        .emit_commit_timestamp(true)
+        .emit_author_date(true)
+        .emit_short(true)
+        .emit_dirty(true);
+    let (res, out) = emit_with_env(builder, &[]);
+    res.map(|revision| (revision, out))
+}
+
+#[test]
+fn test_metadata() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    git(git_dir, &["checkout", "-b", "feature"]);
+    git(git_dir, &["tag", "v1.0"]);
+    commit_with_dates(
+        git_dir,
+        "second",
+        "2022-01-02T03:04:05+01:30",
+        "2021-12-31T23:59:59-08:00",
+    );
+    let sha = git(git_dir, &["rev-parse", "HEAD"]);
+    let short = &sha[..7];
+
+    let (revision, out) = try_init_with_metadata(git_dir, super::Backend::Command).unwrap();
+    assert_eq!(revision.branch.as_deref(), Some("feature"));
+    assert_eq!(revision.tag, None);
+    assert_eq!(revision.commit_timestamp, Some(1641087245));
+    let expected = format!(
+        "cargo:rerun-if-changed=.git/index
+cargo:rerun-if-changed=.git/HEAD
+cargo:rerun-if-changed=.git/refs/heads/feature
+cargo:rustc-env=GIT_REVISION={sha}
+cargo:rustc-env=GIT_REVISION_SHORT={short}
+cargo:rustc-env=GIT_DIRTY=false
+cargo:rustc-env=GIT_BRANCH=feature
+cargo:rustc-env=GIT_DESCRIBE=v1.0-1-g{short}
+cargo:rustc-env=GIT_COMMIT_TIMESTAMP=1641087245
+cargo:rustc-env=GIT_COMMIT_TIMESTAMP_RFC3339=2022-01-02T03:04:05+01:30
+cargo:rustc-env=GIT_COMMIT_AUTHOR_DATE=2021-12-31T23:59:59-08:00
+"
+    );
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_metadata_detached_dirty() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let sha = git(git_dir, &["rev-parse", "HEAD"]);
+    git(git_dir, &["tag", "v1.1"]);
+    git(git_dir, &["tag", "-a", "release", "-m", "release"]);
+    git(git_dir, &["checkout", "--detach"]);
+    fs::write(git_dir.join("readme"), "dirty").unwrap();
+
+    let (revision, out) = try_init_with_metadata(git_dir, super::Backend::Command).unwrap();
+    assert_eq!(revision.sha, sha);
+    assert_eq!(revision.branch, None);
+    assert_eq!(revision.tag.as_deref(), Some("release"));
+    assert!(out.contains("cargo:rustc-env=GIT_DIRTY=true\n"));
+    assert!(out.contains("cargo:rustc-env=GIT_TAG=release\n"));
+    assert!(!out.contains("GIT_BRANCH"));
+}
+
+#[test]
+fn test_metadata_published() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    let vcs_info = r#"{
+  "git": {
+    "sha1": "0c5255b6f47649305fcb68edccb285510aec71a7"
+  },
+  "path_in_vcs": ""
+}"#;
+
+    let file = crate_dir.join(".cargo_vcs_info.json");
+    fs::write(file, vcs_info).unwrap();
+
+    let (_, out) = try_init_with_metadata(crate_dir, super::Backend::Command).unwrap();
+    let expected = "cargo:rustc-env=GIT_REVISION=0c5255b6f47649305fcb68edccb285510aec71a7
+cargo:rustc-env=GIT_REVISION_SHORT=0c5255b
+cargo:rustc-env=GIT_DIRTY=false
+cargo:rustc-env=GIT_PATH_IN_VCS=
+";
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_files_backend_metadata() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    git(git_dir, &["checkout", "-b", "feature"]);
+
+    let (revision, _) = try_init_with_metadata(git_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.branch.as_deref(), Some("feature"));
+    assert_eq!(revision.commit_timestamp, None);
+
+    git(git_dir, &["checkout", "--detach"]);
+    let (revision, _) = try_init_with_metadata(git_dir, super::Backend::Files).unwrap();
+    assert_eq!(revision.branch, None);
+}
+
+#[test]
+fn test_git_revision() {
+    use super::ParseGitRevisionError;
+
+    const CLEAN: GitRevision = GitRevision::new("0c5255b6f47649305fcb68edccb285510aec71a7");
+    assert_eq!(CLEAN.sha(), "0c5255b6f47649305fcb68edccb285510aec71a7");
+    assert_eq!(CLEAN.short(7), "0c5255b");
+    assert_eq!(CLEAN.short(100), CLEAN.sha());
+    assert!(!CLEAN.is_dirty());
+    assert_eq!(
+        CLEAN.to_string(),
+        "0c5255b6f47649305fcb68edccb285510aec71a7"
+    );
+
+    let dirty = GitRevision::parse("0c5255b6f47649305fcb68edccb285510aec71a7-dirty").unwrap();
+    assert_eq!(dirty.sha(), CLEAN.sha());
+    assert!(dirty.is_dirty());
+    assert_ne!(dirty, CLEAN);
+    assert_eq!(
+        dirty.to_string(),
+        "0c5255b6f47649305fcb68edccb285510aec71a7-dirty"
+    );
+
+    let sha256 = "8d1a6b5f3e0f2c9a7b4e6d1c0a9f8e7d6c5b4a39281706f5e4d3c2b1a0f9e8d7";
+    let revision = GitRevision::parse(sha256).unwrap();
+    assert_eq!(revision.sha(), sha256);
+
+    assert_eq!(
+        GitRevision::parse("0c5255b"),
+        Err(ParseGitRevisionError::InvalidLength(7))
+    );
+    assert_eq!(
+        GitRevision::parse("0c5255b6f47649305fcb68edccb285510aec71a7-modified"),
+        Err(ParseGitRevisionError::InvalidLength(49))
+    );
+    assert_eq!(
+        GitRevision::parse("0C5255b6f47649305fcb68edccb285510aec71a7"),
+        Err(ParseGitRevisionError::InvalidCharacter(1))
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid git revision")]
+fn test_git_revision_new_invalid() {
+    let _ = GitRevision::new("unknown");
+}
+
+#[test]
+fn test_git_revision_emitted() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    fs::write(git_dir.join("readme"), "dirty").unwrap();
+
+    let mut out = Vec::new();
+    let revision = super::Builder::new()
+        .manifest_dir(git_dir)
+        .try_emit_to_with_env(&mut out, &no_env)
+        .unwrap();
+    let emitted = revision.to_string_with_suffix(GitRevision::DIRTY_SUFFIX);
+    let parsed = GitRevision::parse(&emitted).unwrap();
+    assert_eq!(parsed.sha(), revision.sha);
+    assert_eq!(parsed.is_dirty(), revision.dirty);
+}
+
+#[test]
+fn test_git_revision_macro() {
+    // CARGO_PKG_NAME stands in for a variable set by the build script, since
+    // this crate has no build script.
+    const REVISION: &str = crate::git_revision!("CARGO_PKG_NAME");
+    assert_eq!(REVISION, "crate-git-revision");
+
+    const BUILD_INFO: super::BuildInfo = crate::build_info!("CARGO_PKG_NAME");
+    assert_eq!(BUILD_INFO.revision, "crate-git-revision");
+    assert_eq!(BUILD_INFO.branch, None);
+    assert_eq!(
+        BUILD_INFO.git_revision(),
+        Err(super::ParseGitRevisionError::InvalidLength(18))
+    );
+}
+
+#[test]
+fn test_generate_source() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+    let out_dir = tempfile::tempdir().unwrap();
+
+    let vcs_info = r#"{
+  "git": {
+    "sha1": "0c5255b6f47649305fcb68edccb285510aec71a7",
+    "dirty": true
+  },
+  "path_in_vcs": "crates/\"example\""
+}"#;
+
+    let file = crate_dir.join(".cargo_vcs_info.json");
+    fs::write(file, vcs_info).unwrap();
+
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir)
+        .out_dir(out_dir.path())
+        .generate_source(true);
+    emit_with_env(builder, &[]).0.unwrap();
+    let source = fs::read_to_string(out_dir.path().join("git_revision.rs")).unwrap();
+    let expected = r#"// Generated by crate-git-revision. Do not edit.
+
+/// The git revision, with the dirty suffix if the working directory was dirty.
+pub const REVISION: &str = "0c5255b6f47649305fcb68edccb285510aec71a7-dirty";
+/// Full hex encoded commit hash.
+pub const SHA: &str = "0c5255b6f47649305fcb68edccb285510aec71a7";
+/// Short form of the commit hash.
+pub const SHORT: &str = "0c5255b";
+/// Whether the working directory had changes not in the commit.
+pub const DIRTY: bool = true;
+/// Name of the branch checked out.
+pub const BRANCH: Option<&str> = None;
+/// Name of a tag pointing at the commit.
+pub const TAG: Option<&str> = None;
+/// Output of `git describe --tags --always` for the commit.
+pub const DESCRIBE: Option<&str> = None;
+/// Committer date of the commit, in seconds since the unix epoch.
+pub const COMMIT_TIMESTAMP: Option<i64> = None;
+/// Committer date of the commit, in RFC 3339 format.
+pub const COMMIT_DATE: Option<&str> = None;
+/// Author date of the commit, in RFC 3339 format.
+pub const AUTHOR_DATE: Option<&str> = None;
+/// Path of the crate relative to the root of the repository.
+pub const PATH_IN_VCS: Option<&str> = Some("crates/\"example\"");
+/// Hash of the last commit that changed the crate's directory.
+pub const CRATE_REVISION: Option<&str> = None;
+/// Hash of the tree of the crate's directory in the commit.
+pub const CRATE_TREE: Option<&str> = None;
+/// Where the crate was built from: `registry`, `git`, `path` or `workspace`.
+pub const REVISION_SOURCE: Option<&str> = None;
+/// Object format of the repository: `sha1` or `sha256`.
+pub const OBJECT_FORMAT: Option<&str> = Some("sha1");
+"#;
+    assert_eq!(source, expected);
+}
+
+#[test]
+fn test_generate_source_metadata() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    let out_dir = tempfile::tempdir().unwrap();
+
+    init_git_repo(git_dir);
+    git(git_dir, &["checkout", "-b", "feature"]);
+    commit_with_dates(
+        git_dir,
+        "second",
+        "2022-01-02T03:04:05+01:30",
+        "2021-12-31T23:59:59-08:00",
+    );
+
+    let builder = super::Builder::new()
+        .manifest_dir(git_dir)
+        .out_dir(out_dir.path())
+        .generate_source(true)
+        .emit_branch(true)
+        .emit_commit_timestamp(true);
+    emit_with_env(builder, &[]).0.unwrap();
+    let source = fs::read_to_string(out_dir.path().join("git_revision.rs")).unwrap();
+    assert!(source.contains("pub const DIRTY: bool = false;\n"));
+    assert!(source.contains("pub const BRANCH: Option<&str> = Some(\"feature\");\n"));
+    assert!(source.contains("pub const COMMIT_TIMESTAMP: Option<i64> = Some(1641087245);\n"));
+    assert!(source.contains("pub const AUTHOR_DATE: Option<&str> = None;\n"));
+}
+
+fn rerun_if_changed(manifest_dir: &Path, backend: super::Backend) -> Vec<String> {
+    let mut out = Vec::new();
+    super::Builder::new()
+        .manifest_dir(manifest_dir)
+        .backend(backend)
+        .try_emit_to_with_env(&mut out, &no_env)
+        .unwrap();
+    String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("cargo:rerun-if-changed="))
+        .map(str::to_string)
+        .collect()
+}
+
+fn backends() -> Vec<super::Backend> {
+    vec![
+        super::Backend::Command,
+        super::Backend::Files,
+        #[cfg(feature = "gix")]
+        super::Backend::Gix,
+        #[cfg(feature = "git2")]
+        super::Backend::Git2,
+    ]
+}
+
+#[test]
+fn test_rerun_if_changed_worktree() {
+    let tempdir = tempfile::tempdir().unwrap();
+    // Paths reported by git have symlinks resolved.
+    let root = fs::canonicalize(tempdir.path()).unwrap();
+    let git_dir = root.join("main");
+    let worktree_dir = root.join("worktree");
+    fs::create_dir(&git_dir).unwrap();
+
+    init_git_repo(&git_dir);
+    git(
+        &git_dir,
+        &[
+            "worktree",
+            "add",
+            "-b",
+            "other",
+            worktree_dir.to_str().unwrap(),
+        ],
+    );
+    git(&git_dir, &["pack-refs", "--all"]);
+
+    let manifest_dir = worktree_dir.join("subdir");
+    fs::create_dir(&manifest_dir).unwrap();
+
+    let worktree_git_dir = git_dir.join(".git/worktrees/worktree");
+    let common_dir = git_dir.join(".git");
+    let expected = vec![
+        format!("{}/index", worktree_git_dir.display()),
+        format!("{}/HEAD", worktree_git_dir.display()),
+        format!("{}/refs/heads", common_dir.display()),
+        format!("{}/packed-refs", common_dir.display()),
+    ];
+    for backend in backends() {
+        assert_eq!(
+            rerun_if_changed(&worktree_dir, backend),
+            expected,
+            "{backend:?}"
+        );
+        assert_eq!(
+            rerun_if_changed(&manifest_dir, backend),
+            expected,
+            "{backend:?}"
+        );
+    }
+}
+
+#[test]
+fn test_rerun_if_changed_worktree_commit() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path().join("main");
+    let worktree_dir = tempdir.path().join("worktree");
+    fs::create_dir(&git_dir).unwrap();
+
+    init_git_repo(&git_dir);
+    git(
+        &git_dir,
+        &[
+            "worktree",
+            "add",
+            "-b",
+            "other",
+            worktree_dir.to_str().unwrap(),
+        ],
+    );
+
+    // Committing in the worktree changes the branch's ref in the common dir,
+    // which must be watched.
+    let watched = rerun_if_changed(&worktree_dir, super::Backend::Command);
+    let ref_file = fs::canonicalize(&git_dir)
+        .unwrap()
+        .join(".git/refs/heads/other");
+    let before = fs::read_to_string(&ref_file).unwrap();
+    fs::write(worktree_dir.join("readme"), "other").unwrap();
+    git(&worktree_dir, &["commit", "-am", "other"]);
+    assert_ne!(before, fs::read_to_string(&ref_file).unwrap());
+    assert!(watched.iter().any(|path| ref_file == Path::new(path)));
+}
+
+#[test]
+fn test_rerun_if_changed_checked_out_ref() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    git(git_dir, &["checkout", "-b", "feature"]);
+    git(git_dir, &["tag", "v1.0"]);
+    git(git_dir, &["branch", "other"]);
+
+    // Only the checked out branch is watched, not other branches or tags.
+    for backend in backends() {
+        let watched = rerun_if_changed(git_dir, backend);
+        let git_dir = Path::new(&watched[0]).parent().unwrap();
+        let expected = vec![
+            format!("{}/index", git_dir.display()),
+            format!("{}/HEAD", git_dir.display()),
+            format!("{}/refs/heads/feature", git_dir.display()),
+        ];
+        assert_eq!(watched, expected, "{backend:?}");
+    }
+
+    // Packing removes the loose ref file, so the directory it would be
+    // recreated in is watched instead.
+    git(git_dir, &["pack-refs", "--all"]);
+    let watched = rerun_if_changed(git_dir, super::Backend::Command);
+    assert_eq!(
+        watched,
+        [
+            ".git/index",
+            ".git/HEAD",
+            ".git/refs/heads",
+            ".git/packed-refs"
+        ]
+    );
+
+    // A detached HEAD contains the commit, and no refs are watched.
+    git(git_dir, &["checkout", "--detach"]);
+    let watched = rerun_if_changed(git_dir, super::Backend::Command);
+    assert_eq!(watched, [".git/index", ".git/HEAD", ".git/packed-refs"]);
+}
+
+#[test]
+fn test_revision_file() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    let out_dir = tempfile::tempdir().unwrap();
+    let revision_file = out_dir.path().join("git_revision.txt");
+
+    init_git_repo(git_dir);
+    let sha = git(git_dir, &["rev-parse", "HEAD"]);
+
+    let builder = super::Builder::new()
+        .manifest_dir(git_dir)
+        .out_dir(out_dir.path())
+        .revision_file(true);
+    let mut out = Vec::new();
+    builder.try_emit_to_with_env(&mut out, &no_env).unwrap();
+    assert_eq!(fs::read_to_string(&revision_file).unwrap(), sha);
+    // The index is not watched, so staging files does not rerun the build
+    // script, and the tracked files are watched instead.
+    let out = String::from_utf8(out).unwrap();
+    assert!(!out.contains(".git/index"));
+    assert!(out.contains("cargo:rerun-if-changed=readme\n"), "{out}");
+
+    fs::write(git_dir.join("readme"), "dirty").unwrap();
+    builder
+        .try_emit_to_with_env(&mut Vec::new(), &no_env)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(&revision_file).unwrap(),
+        format!("{sha}-dirty")
+    );
+    let modified = fs::metadata(&revision_file).unwrap().modified().unwrap();
+
+    // Rerunning without the revision changing does not write the file.
+    git(git_dir, &["add", "readme"]);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    builder
+        .try_emit_to_with_env(&mut Vec::new(), &no_env)
+        .unwrap();
+    assert_eq!(
+        fs::metadata(&revision_file).unwrap().modified().unwrap(),
+        modified
+    );
+}
+
+#[test]
+fn test_write_if_changed() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("file");
+
+    assert!(super::source::write_if_changed(&path, "a").unwrap());
+    assert!(!super::source::write_if_changed(&path, "a").unwrap());
+    assert!(super::source::write_if_changed(&path, "b").unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "b");
+}
+
+#[test]
+fn test_watch_tracked_files() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let crate_dir = git_dir.join("crate");
+    fs::create_dir_all(crate_dir.join("src")).unwrap();
+    fs::create_dir(git_dir.join("other")).unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+    fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    fs::write(git_dir.join("other/file"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "files"]);
+    // Untracked files are not watched.
+    fs::write(crate_dir.join("untracked"), "").unwrap();
+
+    for backend in backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .watch_tracked_files(true);
+        let (res, out) = emit_with_env(builder, &[]);
+        res.unwrap();
+        let watched = out
+            .lines()
+            .filter_map(|line| line.strip_prefix("cargo:rerun-if-changed="))
+            .filter(|path| !path.contains(".git"))
+            .collect::<Vec<_>>();
+        let expected: &[&str] = match backend {
+            super::Backend::Files => &[],
+            _ => &["Cargo.toml", "src/lib.rs"],
+        };
+        assert_eq!(watched, expected, "{backend:?}");
+    }
+}
+
+#[test]
+fn test_watch_tracked_files_deleted() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    fs::write(git_dir.join("Cargo.toml"), "").unwrap();
+    fs::write(git_dir.join("deleted"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "files"]);
+    // Deleted but still in the index.
+    fs::remove_file(git_dir.join("deleted")).unwrap();
+
+    for backend in backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(git_dir)
+            .backend(backend)
+            .watch_tracked_files(true);
+        let (res, out) = emit_with_env(builder, &[]);
+        res.unwrap();
+        let watched = out
+            .lines()
+            .filter_map(|line| line.strip_prefix("cargo:rerun-if-changed="))
+            .filter(|path| !path.contains(".git"))
+            .collect::<Vec<_>>();
+        let expected: &[&str] = match backend {
+            super::Backend::Files => &[],
+            _ => &["Cargo.toml", "readme"],
+        };
+        assert_eq!(watched, expected, "{backend:?}");
+    }
+}
+
+#[test]
+fn test_dirty_crate_only() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let crate_dir = git_dir.join("crate");
+    fs::create_dir_all(crate_dir.join("src")).unwrap();
+    fs::create_dir(git_dir.join("crate2")).unwrap();
+    fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    fs::write(git_dir.join("crate2/lib.rs"), "").unwrap();
+    fs::write(git_dir.join("Cargo.lock"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "files"]);
+
+    let dirty = |backend, manifest_dir: &Path, lock_file: bool| {
+        let mut builder = super::Builder::new()
+            .manifest_dir(manifest_dir)
+            .backend(backend)
+            .dirty_crate_only(true);
+        if lock_file {
+            builder = builder.dirty_path("../Cargo.lock");
+        }
+        builder
+            .try_emit_to_with_env(&mut Vec::new(), &no_env)
+            .unwrap()
+            .dirty
+    };
+
+    for backend in backends() {
+        if backend == super::Backend::Files {
+            continue;
+        }
+
+        // Changes outside the crate, including in a directory sharing its
+        // name as a prefix, are ignored, whether staged or not.
+        fs::write(git_dir.join("crate2/lib.rs"), "changed").unwrap();
+        assert!(!dirty(backend, &crate_dir, true), "{backend:?}");
+        git(git_dir, &["add", "crate2/lib.rs"]);
+        assert!(!dirty(backend, &crate_dir, true), "{backend:?}");
+        // A crate at the root of the repository considers all changes.
+        assert!(dirty(backend, git_dir, false), "{backend:?}");
+        git(git_dir, &["reset", "--hard"]);
+
+        // Extra paths are considered when added.
+        fs::write(git_dir.join("Cargo.lock"), "changed").unwrap();
+        assert!(!dirty(backend, &crate_dir, false), "{backend:?}");
+        assert!(dirty(backend, &crate_dir, true), "{backend:?}");
+        git(git_dir, &["reset", "--hard"]);
+
+        fs::write(crate_dir.join("src/lib.rs"), "changed").unwrap();
+        assert!(dirty(backend, &crate_dir, false), "{backend:?}");
+        git(git_dir, &["add", "crate/src/lib.rs"]);
+        assert!(dirty(backend, &crate_dir, false), "{backend:?}");
+        git(git_dir, &["reset", "--hard"]);
+
+        assert!(!dirty(backend, &crate_dir, true), "{backend:?}");
+    }
+}
+
+/// A repository containing a crate in a subdirectory, with a change in the
+/// crate and a staged change outside it.
+fn dirty_repository() -> (tempfile::TempDir, PathBuf) {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let crate_dir = git_dir.join("crate");
+    fs::create_dir(&crate_dir).unwrap();
+    fs::write(crate_dir.join("lib.rs"), "").unwrap();
+    fs::write(git_dir.join("other.rs"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "files"]);
+
+    fs::write(crate_dir.join("lib.rs"), "changed").unwrap();
+    fs::write(git_dir.join("readme"), "changed").unwrap();
+    git(git_dir, &["add", "readme"]);
+    (tempdir, crate_dir)
+}
+
+/// The backends that read the index and config, which detect changes in the
+/// working directory.
+fn git_backends() -> Vec<super::Backend> {
+    backends()
+        .into_iter()
+        .filter(|backend| *backend != super::Backend::Files)
+        .collect()
+}
+
+#[test]
+fn test_require_clean() {
+    let tempdir = tempfile::tempdir().unwrap();
+    init_git_repo(tempdir.path());
+
+    // Clean revisions are allowed.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(tempdir.path())
+            .backend(backend)
+            .require_clean(super::RequireClean::Always);
+        let (res, _) = emit_with_env(builder, &[("PROFILE", "release")]);
+        assert!(!res.unwrap().dirty, "{backend:?}");
+    }
+}
+
+#[test]
+fn test_require_clean_dirty() {
+    let (_tempdir, crate_dir) = dirty_repository();
+
+    // Modified files are listed relative to the root of the repository,
+    // whether staged or not.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .require_clean(super::RequireClean::Release);
+        match emit_with_env(builder, &[("PROFILE", "release")]).0 {
+            Err(super::Error::Dirty { paths }) => assert_eq!(
+                paths,
+                [PathBuf::from("crate/lib.rs"), PathBuf::from("readme")],
+                "{backend:?}"
+            ),
+            res => panic!("{backend:?}: {res:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_require_clean_profile() {
+    use super::RequireClean::{Always, Never, Release};
+    let (_tempdir, crate_dir) = dirty_repository();
+
+    for backend in git_backends() {
+        let emit = |require_clean, profile| {
+            let builder = super::Builder::new()
+                .manifest_dir(&crate_dir)
+                .backend(backend)
+                .require_clean(require_clean);
+            emit_with_env(builder, &[("PROFILE", profile)]).0
+        };
+        assert!(emit(Release, "debug").unwrap().dirty, "{backend:?}");
+        assert!(emit(Never, "release").unwrap().dirty, "{backend:?}");
+        assert!(emit(Always, "debug").is_err(), "{backend:?}");
+    }
+}
+
+#[test]
+fn test_require_clean_env() {
+    use super::RequireClean::{Never, Release};
+    let (_tempdir, crate_dir) = dirty_repository();
+
+    // The environment variable overrides the builder.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend);
+        let vars = [
+            ("PROFILE", "debug"),
+            ("CRATE_GIT_REVISION_REQUIRE_CLEAN", "1"),
+        ];
+        let (res, _) = emit_with_env(builder.clone().require_clean(Never), &vars);
+        assert!(res.is_err(), "{backend:?}");
+        let vars = [
+            ("PROFILE", "release"),
+            ("CRATE_GIT_REVISION_REQUIRE_CLEAN", "0"),
+        ];
+        let (res, out) = emit_with_env(builder.require_clean(Release), &vars);
+        assert!(res.unwrap().dirty, "{backend:?}");
+        assert!(out.contains("cargo:rerun-if-env-changed=CRATE_GIT_REVISION_REQUIRE_CLEAN\n"));
+    }
+}
+
+#[test]
+fn test_require_clean_dirty_crate_only() {
+    let (_tempdir, crate_dir) = dirty_repository();
+
+    // Only the changes considered are listed.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .require_clean(super::RequireClean::Always)
+            .dirty_crate_only(true);
+        match emit_with_env(builder, &[]).0 {
+            Err(super::Error::Dirty { paths }) => {
+                assert_eq!(paths, [PathBuf::from("crate/lib.rs")], "{backend:?}")
+            }
+            res => panic!("{backend:?}: {res:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_require_clean_no_fallback() {
+    let (_tempdir, crate_dir) = dirty_repository();
+
+    // The error fails the build rather than falling back.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .require_clean(super::RequireClean::Always);
+        let (res, _) = emit_fallback_with_env(builder, &[]);
+        assert!(
+            matches!(res, Err(super::Error::Dirty { .. })),
+            "{backend:?}"
+        );
+    }
+}
+
+#[test]
+fn test_require_clean_files_backend() {
+    let tempdir = tempfile::tempdir().unwrap();
+    init_git_repo(tempdir.path());
+
+    // Changes are not detected, so even a clean working directory fails.
+    let builder = super::Builder::new()
+        .manifest_dir(tempdir.path())
+        .backend(super::Backend::Files);
+    let (res, _) = emit_with_env(
+        builder.clone().require_clean(super::RequireClean::Always),
+        &[],
+    );
+    assert!(matches!(res, Err(super::Error::CleanUnverified)));
+    let (res, _) = emit_fallback_with_env(
+        builder.clone().require_clean(super::RequireClean::Always),
+        &[],
+    );
+    assert!(matches!(res, Err(super::Error::CleanUnverified)));
+
+    // Builds that do not require a clean working directory are not affected.
+    let (res, _) = emit_with_env(
+        builder.require_clean(super::RequireClean::Release),
+        &[("PROFILE", "debug")],
+    );
+    assert!(!res.unwrap().dirty);
+}
+
+/// A repository with the remote, containing a crate in `vendor/example`
+/// that is not tracked.
+fn vendoring_repository(remote: &str) -> (tempfile::TempDir, PathBuf) {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    git(git_dir, &["remote", "add", "origin", remote]);
+    let crate_dir = git_dir.join("vendor/example");
+    fs::create_dir_all(&crate_dir).unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+    (tempdir, crate_dir)
+}
+
+/// Track the crate in the repository, returning the commit.
+fn track_vendored(git_dir: &Path) -> String {
+    git(git_dir, &["add", "vendor"]);
+    git(git_dir, &["commit", "-m", "vendor"]);
+    git(git_dir, &["rev-parse", "HEAD"])
+}
+
+const VENDORED_CI_SHA: &str = "0c5255b6f47649305fcb68edccb285510aec71a7";
+
+/// Emit for the crate with the repository in its manifest, with a CI
+/// revision for when the repository containing it is ignored.
+fn emit_vendored(builder: super::Builder, repository: &str) -> (String, String) {
+    let vars = [
+        ("CARGO_PKG_REPOSITORY", repository),
+        ("GITHUB_SHA", VENDORED_CI_SHA),
+    ];
+    let (res, out) = emit_with_env(builder.ci_revision(true), &vars);
+    (res.unwrap().sha, out)
+}
+
+#[test]
+fn test_vendored_untracked() {
+    let (tempdir, crate_dir) = vendoring_repository("git@github.com:owner/host.git");
+    let head = git(tempdir.path(), &["rev-parse", "HEAD"]);
+
+    // A crate in the repository without being added to it, such as one just
+    // created, uses the repository unless untracked crates are detected, in
+    // which case it falls back to the next source.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend);
+        assert_eq!(emit_vendored(builder.clone(), "").0, head, "{backend:?}");
+        let (sha, out) = emit_vendored(builder.detect_untracked(true), "");
+        assert_eq!(sha, VENDORED_CI_SHA, "{backend:?}");
+        assert!(out.starts_with("cargo:warning=Ignoring the git repository containing the crate, as the crate's Cargo.toml is not tracked in it\n"));
+    }
+
+    let head = track_vendored(tempdir.path());
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .detect_untracked(true);
+        assert_eq!(emit_vendored(builder, "").0, head, "{backend:?}");
+    }
+}
+
+#[test]
+fn test_vendored() {
+    let (tempdir, crate_dir) = vendoring_repository("git@github.com:owner/host.git");
+    track_vendored(tempdir.path());
+
+    // A crate whose repository does not match a remote falls back to the next
+    // source.
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .detect_vendored(true);
+        let (sha, out) = emit_vendored(builder, "https://github.com/other/example");
+        assert_eq!(sha, VENDORED_CI_SHA, "{backend:?}");
+        assert!(out.contains("as the crate's repository https://github.com/other/example does not match its remotes\n"));
+    }
+}
+
+#[test]
+fn test_vendored_matching_remote() {
+    let (tempdir, crate_dir) = vendoring_repository("git@github.com:owner/host.git");
+    let head = track_vendored(tempdir.path());
+
+    for backend in git_backends() {
+        for repository in [
+            "https://github.com/owner/host",
+            "https://github.com/owner/host/tree/main/vendor/example",
+            "https://gitlab.com/fork/Host.git",
+        ] {
+            let builder = super::Builder::new()
+                .manifest_dir(&crate_dir)
+                .backend(backend)
+                .detect_vendored(true);
+            assert_eq!(emit_vendored(builder, repository).0, head, "{backend:?}");
+        }
+    }
+}
+
+#[test]
+fn test_vendored_not_detected_by_default() {
+    let (tempdir, crate_dir) = vendoring_repository("git@github.com:owner/host.git");
+    let head = track_vendored(tempdir.path());
+
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend);
+        let (sha, out) = emit_vendored(builder, "https://github.com/other/example");
+        assert_eq!(sha, head, "{backend:?}");
+        assert!(!out.contains("cargo:warning"));
+    }
+}
+
+#[test]
+fn test_vendored_cargo_git_checkout() {
+    // Cargo checks out git dependencies, such as with `cargo install --git`,
+    // from its clone in `$CARGO_HOME/git/db`, which is the checkout's remote.
+    let cargo_home = tempfile::tempdir().unwrap();
+    let db = cargo_home.path().join("git/db/host-0123456789abcdef");
+    let (tempdir, crate_dir) = vendoring_repository(&format!("file://{}", db.display()));
+    let head = track_vendored(tempdir.path());
+
+    for backend in git_backends() {
+        let builder = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .detect_vendored(true);
+        let (sha, _) = emit_vendored(builder, "https://github.com/owner/host");
+        assert_eq!(sha, head, "{backend:?}");
+    }
+}
+
+#[test]
+fn test_vendored_matches_remotes() {
+    let matches = |repository, remote: &str| {
+        super::vendored::matches_remotes(repository, &[remote.to_string()])
+    };
+    assert!(matches(
+        "https://github.com/owner/repo",
+        "https://github.com/owner/repo.git"
+    ));
+    assert!(matches(
+        "https://github.com/owner/repo",
+        "git@github.com:owner/repo.git"
+    ));
+    assert!(matches(
+        "https://github.com/owner/repo",
+        "ssh://git@github.com/fork/repo"
+    ));
+    assert!(matches("https://github.com/owner/repo/", "/home/user/repo"));
+    assert!(matches(
+        "https://github.com/owner/repo/tree/main/crates/x",
+        "https://github.com/owner/repo"
+    ));
+    assert!(!matches(
+        "https://github.com/owner/repo",
+        "https://github.com/owner/other"
+    ));
+    assert!(!matches(
+        "https://github.com/owner/repo",
+        "https://github.com/repo/other"
+    ));
+    assert!(!matches("https://github.com/owner/repo", ""));
+    // Cargo's clones of git dependencies.
+    assert!(matches(
+        "https://github.com/owner/repo",
+        "file:///home/user/.cargo/git/db/repo-0123456789abcdef"
+    ));
+    assert!(matches(
+        "https://github.com/owner/repo",
+        "C:\\Users\\user\\.cargo\\git\\db\\repo-0123456789abcdef"
+    ));
+    assert!(!matches(
+        "https://github.com/owner/repo",
+        "/home/user/.cargo/git/db/other-0123456789abcdef"
+    ));
+    assert!(!matches(
+        "https://github.com/owner/repo",
+        "/home/user/repo-0123456789abcdef"
+    ));
+}
+
+#[test]
+fn test_revision_source() {
+    use super::SourceKind;
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path();
+    let vcs_info = r#"{"git":{"sha1":"0c5255b6f47649305fcb68edccb285510aec71a7"}}"#;
+
+    let source_kind = |manifest_dir: &Path, out_dir: Option<&Path>| {
+        let out_dir = out_dir.map(|dir| dir.display().to_string());
+        let vars = out_dir.as_deref().map(|dir| ("OUT_DIR", dir));
+        let builder = super::Builder::new()
+            .manifest_dir(manifest_dir)
+            .emit_revision_source(true);
+        let (res, out) = emit_with_env(builder, vars.as_slice());
+        let revision = res.unwrap();
+        let kind = revision.source_kind.unwrap();
+        assert!(out.ends_with(&format!("cargo:rustc-env=GIT_REVISION_SOURCE={kind}\n")));
+        kind
+    };
+
+    // A workspace, with the target directory in its root.
+    let workspace_dir = root.join("workspace");
+    let crate_dir = workspace_dir.join("crates/example");
+    fs::create_dir_all(&crate_dir).unwrap();
+    init_git_repo(&workspace_dir);
+    fs::write(workspace_dir.join("Cargo.toml"), "").unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+    git(&workspace_dir, &["add", "."]);
+    git(&workspace_dir, &["commit", "-m", "crate"]);
+    let out_dir = workspace_dir.join("target/debug/build/example-0123456789abcdef/out");
+    assert_eq!(
+        source_kind(&crate_dir, Some(&out_dir)),
+        SourceKind::Workspace
+    );
+    // Built as a path dependency of another project.
+    let out_dir = root.join("project/target/debug/build/example-0123456789abcdef/out");
+    assert_eq!(source_kind(&crate_dir, Some(&out_dir)), SourceKind::Path);
+    assert_eq!(source_kind(&crate_dir, None), SourceKind::Path);
+
+    // A git dependency checked out by cargo.
+    let checkout_dir = root.join("cargo/git/checkouts/example-0123456789abcdef/0c5255b");
+    let crate_dir = checkout_dir.join("crates/example");
+    fs::create_dir_all(&crate_dir).unwrap();
+    init_git_repo(&checkout_dir);
+    let out_dir = root.join("project/target/debug/build/example-0123456789abcdef/out");
+    assert_eq!(source_kind(&crate_dir, Some(&out_dir)), SourceKind::Git);
+
+    // A package from a registry, or vendored from one.
+    let crate_dir = root.join("cargo/registry/src/index.crates.io-0123456789abcdef/example-0.1.0");
+    let vendor_dir = root.join("project/vendor/example");
+    for dir in [&crate_dir, &vendor_dir] {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(".cargo_vcs_info.json"), vcs_info).unwrap();
+        assert_eq!(source_kind(dir, Some(&out_dir)), SourceKind::Registry);
+    }
+    // Vendored with `cargo vendor` into the workspace, from a package
+    // published without `.cargo_vcs_info.json`.
+    let vendor_dir = workspace_dir.join("vendor/other");
+    fs::create_dir_all(&vendor_dir).unwrap();
+    fs::write(vendor_dir.join(".cargo-checksum.json"), r#"{"files":{}}"#).unwrap();
+    let out_dir = workspace_dir.join("target/debug/build/other-0123456789abcdef/out");
+    assert_eq!(
+        source_kind(&vendor_dir, Some(&out_dir)),
+        SourceKind::Registry
+    );
+
+    // Not emitted unless enabled.
+    let mut out = Vec::new();
+    let revision = super::Builder::new()
+        .manifest_dir(&vendor_dir)
+        .try_emit_to_with_env(&mut out, &no_env)
+        .unwrap();
+    assert_eq!(revision.source_kind, None);
+    assert!(!String::from_utf8(out)
+        .unwrap()
+        .contains("GIT_REVISION_SOURCE"));
+}
+
+/// Assert that the output of the build script does not contain an injected
+/// directive, and that every directive is one expected.
+fn assert_no_injection(out: &str) {
+    for line in out.lines() {
+        assert!(
+            line.starts_with("cargo:rustc-env=")
+                || line.starts_with("cargo:rerun-if-changed=")
+                || line.starts_with("cargo:rerun-if-env-changed=")
+                || line.starts_with("cargo:warning="),
+            "{out}"
+        );
+    }
+    assert!(!out.contains("\ncargo:rustc-link-arg"), "{out}");
+}
+
+#[derive(Debug)]
+struct MaliciousSource(super::Revision);
+
+impl super::RevisionSource for MaliciousSource {
+    fn revision(
+        &self,
+        context: &mut super::SourceContext<'_>,
+    ) -> Result<Option<super::Revision>, super::Error> {
+        context.warning("checked\ncargo:rustc-link-arg=-Wl,--warning");
+        Ok(Some(self.0.clone()))
+    }
+}
+
+#[test]
+fn test_directive_injection() {
+    let injected = "\ncargo:rustc-link-arg=-Wl,--injected";
+    let sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+
+    let emit = |builder: super::Builder| emit_with_env(builder.emit_branch(true), &[]);
+    let fallback = |builder: super::Builder| {
+        let mut out = Vec::new();
+        builder
+            .emit_branch(true)
+            .emit_to_with_env(&mut out, &no_env)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // Git refuses to create refs with control characters, but they can be
+    // written to the git directory directly.
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    init_git_repo(git_dir);
+    let branch = format!("evil{injected}");
+    let output = Command::new("git")
+        .current_dir(git_dir)
+        .args(["branch", &branch])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    fs::write(git_dir.join(".git/refs/heads").join(&branch), &head).unwrap();
+    fs::write(
+        git_dir.join(".git/HEAD"),
+        format!("ref: refs/heads/{branch}\n"),
+    )
+    .unwrap();
+    let builder = || {
+        super::Builder::new()
+            .manifest_dir(git_dir)
+            .backend(super::Backend::Files)
+    };
+    let (res, out) = emit(builder());
+    assert!(
+        matches!(res, Err(super::Error::InvalidDirective { .. })),
+        "{res:?}"
+    );
+    assert!(out.is_empty());
+    let out = fallback(builder());
+    assert_no_injection(&out);
+    assert!(out.ends_with("\ncargo:rustc-env=GIT_REVISION=unknown\n"));
+
+    // Tracked files can contain line breaks in their names.
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    init_git_repo(git_dir);
+    fs::write(git_dir.join(format!("file{injected}")), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "file"]);
+    let builder = || {
+        super::Builder::new()
+            .manifest_dir(git_dir)
+            .watch_tracked_files(true)
+    };
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    let (res, out) = emit(builder());
+    assert_eq!(res.unwrap().sha, head);
+    assert_no_injection(&out);
+    assert!(!out.lines().any(|line| line.starts_with("cargo:rerun-if")));
+    assert!(out.contains(&format!("\ncargo:rustc-env=GIT_REVISION={head}\n")));
+
+    // The path of a published crate is read from a JSON file, which can
+    // contain any characters.
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+    let vcs_info = serde_json::json!({
+        "git": { "sha1": sha },
+        "path_in_vcs": format!("crates/example{injected}"),
+    });
+    fs::write(crate_dir.join(".cargo_vcs_info.json"), vcs_info.to_string()).unwrap();
+    let (res, out) = emit(super::Builder::new().manifest_dir(crate_dir));
+    assert!(matches!(res, Err(super::Error::InvalidDirective { .. })));
+    assert!(out.is_empty());
+    assert_no_injection(&fallback(super::Builder::new().manifest_dir(crate_dir)));
+
+    // Values from sources outside this crate are checked too, and warnings
+    // are escaped.
+    let malicious = |revision: super::Revision| {
+        super::Builder::new()
+            .manifest_dir(crate_dir)
+            .sources(vec![Box::new(MaliciousSource(revision))])
+    };
+    let mut revision = super::Revision::new(sha, false);
+    revision.branch = Some("main".to_string());
+    let (res, out) = emit(malicious(revision.clone()));
+    assert!(res.is_ok());
+    assert_no_injection(&out);
+    assert!(out.starts_with("cargo:warning=checked\\ncargo:rustc-link-arg=-Wl,--warning\n"));
+
+    let mut revisions = Vec::new();
+    for branch in [
+        format!("main{injected}"),
+        "main\rcargo:rustc-link-arg=-Wl,--injected".to_string(),
+        "main..other".to_string(),
+        "main@{0}".to_string(),
+        "main branch".to_string(),
+    ] {
+        let mut revision = revision.clone();
+        revision.branch = Some(branch);
+        revisions.push(revision);
+    }
+    let mut tagged = revision.clone();
+    tagged.tag = Some(format!("v1.0{injected}"));
+    revisions.push(tagged);
+    let mut described = revision.clone();
+    described.describe = Some(format!("v1.0-1-g0c5255b{injected}"));
+    revisions.push(described);
+    let mut dated = revision.clone();
+    dated.commit_date = Some(format!("2024-01-02T03:04:05Z{injected}"));
+    revisions.push(dated);
+    revisions.push(super::Revision::new(format!("{sha}{injected}"), false));
+    revisions.push(super::Revision::new("", false));
+    for revision in revisions {
+        let (res, out) = emit(malicious(revision.clone()));
+        assert!(
+            matches!(res, Err(super::Error::InvalidDirective { .. })),
+            "{revision:?}"
+        );
+        assert_no_injection(&out);
+        assert_no_injection(&fallback(malicious(revision)));
+    }
+
+    // Configuration of the build script is checked too.
+    let (res, _) = emit(malicious(super::Revision::new(sha, true)).dirty_suffix(injected));
+    assert!(matches!(res, Err(super::Error::InvalidDirective { .. })));
+    let (res, _) = emit(malicious(super::Revision::new(sha, false)).env_name("A=B"));
+    assert!(matches!(res, Err(super::Error::InvalidDirective { .. })));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directive_non_unicode_path() {
+    use std::os::unix::ffi::OsStrExt;
+
+    // Paths that are not valid unicode cannot be written to cargo without
+    // loss, and are not watched with a path that does not exist. No rerun
+    // directives are emitted, leaving cargo's default of rerunning when any
+    // file in the crate changes, and the revision is still emitted.
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir
+        .path()
+        .join(std::ffi::OsStr::from_bytes(b"repo\xff"));
+    let crate_dir = git_dir.join("crate");
+    fs::create_dir_all(&crate_dir).unwrap();
+    init_git_repo(&git_dir);
+    let head = git(&git_dir, &["rev-parse", "HEAD"]);
+
+    for backend in backends() {
+        let mut out = Vec::new();
+        let revision = super::Builder::new()
+            .manifest_dir(&crate_dir)
+            .backend(backend)
+            .try_emit_to_with_env(&mut out, &no_env)
+            .unwrap();
+        assert_eq!(revision.sha, head, "{backend:?}");
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with("cargo:warning=Rerunning the build script when any file in the crate changes, refusing to emit cargo:rerun-if-changed with invalid value "),
+            "{backend:?}: {out}"
+        );
+        assert!(out.contains("repo\u{FFFD}"), "{backend:?}: {out}");
+        assert!(
+            !out.lines().any(|line| line.starts_with("cargo:rerun-if")),
+            "{backend:?}"
+        );
+        assert!(out.ends_with(&format!("\ncargo:rustc-env=GIT_REVISION={head}\n")));
+    }
+}
+
+#[test]
+fn test_crate_revision() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    init_git_repo(git_dir);
+    let crate_dir = git_dir.join("crate");
+    let untracked_dir = git_dir.join("untracked");
+    fs::create_dir(&crate_dir).unwrap();
+    fs::create_dir(git_dir.join("other")).unwrap();
+    fs::create_dir(&untracked_dir).unwrap();
+    fs::write(crate_dir.join("lib.rs"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "crate"]);
+    git(git_dir, &["checkout", "-b", "feature"]);
+    fs::write(crate_dir.join("lib.rs"), "feature").unwrap();
+    git(git_dir, &["commit", "-am", "feature"]);
+    let feature = git(git_dir, &["rev-parse", "HEAD"]);
+    git(git_dir, &["checkout", "-"]);
+    fs::write(git_dir.join("other/file"), "").unwrap();
+    git(git_dir, &["add", "."]);
+    git(git_dir, &["commit", "-m", "other"]);
+    // The merge does not change the crate compared to the feature branch.
+    git(git_dir, &["merge", "--no-ff", "-m", "merge", "feature"]);
+    fs::write(git_dir.join("other/file"), "changed").unwrap();
+    git(git_dir, &["commit", "-am", "other changed"]);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    let crate_tree = git(git_dir, &["rev-parse", "HEAD:crate"]);
+    let root_tree = git(git_dir, &["rev-parse", "HEAD^{tree}"]);
+
+    let crate_revision = |manifest_dir: &Path, backend| {
+        let mut out = Vec::new();
+        let revision = super::Builder::new()
+            .manifest_dir(manifest_dir)
+            .backend(backend)
+            .emit_crate_revision(true)
+            .emit_crate_tree(true)
+            .try_emit_to_with_env(&mut out, &no_env)
+            .unwrap();
+        (revision, String::from_utf8(out).unwrap())
+    };
+
+    for backend in backends() {
+        let (revision, out) = crate_revision(&crate_dir, backend);
+        if backend == super::Backend::Files {
+            assert_eq!(revision.crate_revision, None);
+            assert_eq!(revision.crate_tree, None);
+            continue;
+        }
+        assert_eq!(
+            revision.crate_revision.as_ref(),
+            Some(&feature),
+            "{backend:?}"
+        );
+        assert_eq!(
+            revision.crate_tree.as_ref(),
+            Some(&crate_tree),
+            "{backend:?}"
+        );
+        assert!(out.contains(&format!("cargo:rustc-env=GIT_CRATE_REVISION={feature}\n")));
+        assert!(out.contains(&format!("cargo:rustc-env=GIT_CRATE_TREE={crate_tree}\n")));
+
+        let (revision, _) = crate_revision(git_dir, backend);
+        assert_eq!(revision.crate_revision.as_ref(), Some(&head), "{backend:?}");
+        assert_eq!(
+            revision.crate_tree.as_ref(),
+            Some(&root_tree),
+            "{backend:?}"
+        );
+
+        let (revision, out) = crate_revision(&untracked_dir, backend);
+        assert_eq!(revision.crate_revision, None, "{backend:?}");
+        assert_eq!(revision.crate_tree, None, "{backend:?}");
+        assert!(!out.contains("GIT_CRATE"));
+    }
+}
+
+#[test]
+#[cfg(feature = "content-hash")]
+fn test_content_hash_fallback() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let crate_dir = tempdir.path();
+
+    fs::create_dir(crate_dir.join("src")).unwrap();
+    fs::write(
+        crate_dir.join("Cargo.toml"),
+        r#"[package]
+name = "tarball"
+version = "0.1.0"
+exclude = ["excluded"]
+"#,
+    )
+    .unwrap();
+    fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    fs::write(crate_dir.join("excluded"), "").unwrap();
+
+    let content_hash = || {
+        let mut out = Vec::new();
+        super::Builder::new()
+            .manifest_dir(crate_dir)
+            .content_hash_fallback(true)
+            .try_emit_to_with_env(&mut out, &no_env)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let out = content_hash();
+    let re = Regex::new(
+        "^cargo:rerun-if-changed=Cargo.toml
+cargo:rerun-if-changed=src/lib.rs
+cargo:rerun-if-env-changed=CRATE_GIT_REVISION
+cargo:rustc-env=GIT_REVISION=unknown\\+src\\.([0-9a-f]{40})
+$",
+    )
+    .unwrap();
+    assert!(re.is_match(&out), "{out}");
+
+    // Excluded files do not change the hash, package files do.
+    assert_eq!(content_hash(), out);
+    fs::write(crate_dir.join("excluded"), "changed").unwrap();
+    assert_eq!(content_hash(), out);
+    fs::write(crate_dir.join("src/lib.rs"), "changed").unwrap();
+    assert_ne!(content_hash(), out);
+
+    // The fallback is opt-in.
+    let res = super::Builder::new()
+        .manifest_dir(crate_dir)
+        .try_emit_to_with_env(&mut Vec::new(), &no_env);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+}
+
+#[test]
+fn test_git_archival() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path().join("repo");
+    let archive_dir = tempdir.path().join("archive");
+    fs::create_dir(&git_dir).unwrap();
+    fs::create_dir(&archive_dir).unwrap();
+
+    init_git_repo(&git_dir);
+    let archival = "\
+node: $Format:%H$
+node-date: $Format:%cI$
+describe-name: $Format:%(describe:tags=true)$
+ref-names: $Format:%D$
+";
+    fs::write(git_dir.join(".git_archival.txt"), archival).unwrap();
+    fs::write(
+        git_dir.join(".gitattributes"),
+        ".git_archival.txt export-subst\n",
+    )
+    .unwrap();
+    fs::create_dir(git_dir.join("crate")).unwrap();
+    fs::write(git_dir.join("crate/lib.rs"), "").unwrap();
+    git(&git_dir, &["add", "."]);
+    commit_with_dates(
+        &git_dir,
+        "archival",
+        "2022-01-02T03:04:05+01:30",
+        "2022-01-02T03:04:05+01:30",
+    );
+    git(&git_dir, &["tag", "v1.0"]);
+    let sha = git(&git_dir, &["rev-parse", "HEAD"]);
+    let branch = git(&git_dir, &["rev-parse", "--abbrev-ref", "HEAD"]);
+
+    let archive = Command::new("git")
+        .current_dir(&git_dir)
+        .args(["archive", "HEAD"])
+        .output()
+        .unwrap();
+    assert!(archive.status.success());
+    let mut tar = Command::new("tar")
+        .current_dir(&archive_dir)
+        .arg("-x")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(tar.stdin.as_mut().unwrap(), &archive.stdout).unwrap();
+    assert!(tar.wait().unwrap().success());
+
+    // The archival file at the root of the archive is found from the crate.
+    let (revision, out) =
+        try_init_with_metadata(&archive_dir.join("crate"), super::Backend::Command).unwrap();
+    assert_eq!(revision.sha, sha);
+    assert!(!revision.dirty);
+    assert_eq!(revision.branch, Some(branch));
+    assert_eq!(revision.tag.as_deref(), Some("v1.0"));
+    assert_eq!(revision.describe.as_deref(), Some("v1.0"));
+    assert_eq!(revision.commit_timestamp, Some(1641087245));
+    assert_eq!(
+        revision.commit_date.as_deref(),
+        Some("2022-01-02T03:04:05+01:30")
+    );
+    assert!(out.starts_with(&format!(
+        "cargo:rerun-if-changed={}\n\
+         cargo:rerun-if-env-changed=CRATE_GIT_REVISION\n\
+         cargo:rustc-env=GIT_REVISION={sha}\n",
+        archive_dir.join(".git_archival.txt").display()
+    )));
+
+    // An archival file that was not substituted is ignored.
+    fs::write(archive_dir.join(".git_archival.txt"), archival).unwrap();
+    let res = super::Builder::new()
+        .manifest_dir(&archive_dir)
+        .try_emit_to_with_env(&mut Vec::new(), &no_env);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+}
+
+#[test]
+fn test_env_revision() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path().join("repo");
+    let crate_dir = tempdir.path().join("crate");
+    fs::create_dir(&git_dir).unwrap();
+    fs::create_dir(&crate_dir).unwrap();
+    init_git_repo(&git_dir);
+    let head = git(&git_dir, &["rev-parse", "HEAD"]);
+    let env_sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+    let ci_sha = "1d6366c7058750416ebc79feddc396621bfd82b8";
+
+    let emit = |manifest_dir: &Path, ci_revision: bool, vars: &[(&str, &str)]| {
+        let builder = super::Builder::new()
+            .manifest_dir(manifest_dir)
+            .ci_revision(ci_revision);
+        let (res, out) = emit_with_env(builder, vars);
+        res.map(|revision| (revision, out))
+    };
+
+    // CRATE_GIT_REVISION is the revision of crates not in a repository.
+    let (revision, out) = emit(&crate_dir, false, &[("CRATE_GIT_REVISION", env_sha)]).unwrap();
+    assert_eq!(revision.sha, env_sha);
+    assert!(!revision.dirty);
+    assert_eq!(
+        out,
+        format!(
+            "cargo:rerun-if-env-changed=CRATE_GIT_REVISION\n\
+             cargo:rustc-env=GIT_REVISION={env_sha}\n"
+        )
+    );
+    let vars = [("CRATE_GIT_REVISION", &*format!("{env_sha}-dirty"))];
+    let (revision, _) = emit(&crate_dir, false, &vars).unwrap();
+    assert_eq!(revision.sha, env_sha);
+    assert!(revision.dirty);
+    let res = emit(&crate_dir, false, &[("CRATE_GIT_REVISION", "HEAD")]);
+    assert!(matches!(
+        res,
+        Err(super::Error::InvalidEnvRevision { name, .. }) if name == "CRATE_GIT_REVISION"
+    ));
+    let res = emit(&crate_dir, false, &[("CRATE_GIT_REVISION", "")]);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+
+    // Crates in a repository, such as dependencies from other repositories,
+    // keep the revision of their repository.
+    let (revision, out) = emit(&git_dir, false, &[("CRATE_GIT_REVISION", env_sha)]).unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(!out.contains("CRATE_GIT_REVISION"));
+
+    // CI variables are only used when enabled and there is no repository, in
+    // order of precedence, skipping values that are not commit hashes.
+    let vars = [
+        ("GITHUB_SHA", "HEAD"),
+        ("BUILDKITE_COMMIT", ci_sha),
+        ("CIRCLE_SHA1", env_sha),
+    ];
+    let (revision, out) = emit(&crate_dir, true, &vars).unwrap();
+    assert_eq!(revision.sha, ci_sha);
+    assert!(out.contains("cargo:rerun-if-env-changed=BUILDKITE_COMMIT\n"));
+    let (revision, _) = emit(&git_dir, true, &vars).unwrap();
+    assert_eq!(revision.sha, head);
+    let res = emit(&crate_dir, false, &vars);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+
+    // Published crates use the revision they were published with.
+    fs::write(
+        crate_dir.join(".cargo_vcs_info.json"),
+        format!(r#"{{"git":{{"sha1":"{head}"}}}}"#),
+    )
+    .unwrap();
+    let vars = [("CRATE_GIT_REVISION", env_sha), ("GITHUB_SHA", ci_sha)];
+    let (revision, out) = emit(&crate_dir, true, &vars).unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(!out.contains("rerun-if-env-changed"));
+}
+
+#[test]
+fn test_env_revision_unreadable_repository() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    init_git_repo(git_dir);
+    // Git refuses to open the repository, as it would one owned by another
+    // user.
+    git(git_dir, &["config", "core.repositoryformatversion", "99"]);
+    let env_sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+
+    let builder = super::Builder::new()
+        .manifest_dir(git_dir)
+        .backend(super::Backend::Command);
+    let (res, out) = emit_with_env(builder, &[("CRATE_GIT_REVISION", env_sha)]);
+    assert_eq!(res.unwrap().sha, env_sha);
+    assert!(out.contains("cargo:warning=Ignoring the git repository containing the crate"));
+    assert!(out.contains("cargo:rustc-env=GIT_REVISION=0c5255b6f47649305fcb68edccb285510aec71a7\n"));
+}
+
+#[derive(Debug)]
+struct FileSource(&'static str);
+
+impl super::RevisionSource for FileSource {
+    fn revision(
+        &self,
+        context: &mut super::SourceContext<'_>,
+    ) -> Result<Option<super::Revision>, super::Error> {
+        let path = context.manifest_dir().join(self.0);
+        context.rerun_if_changed(self.0);
+        match fs::read_to_string(path) {
+            Ok(sha) if sha.is_empty() => Err(super::Error::Custom("empty".into())),
+            Ok(sha) => Ok(Some(super::Revision::new(sha, false))),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn test_sources() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+    init_git_repo(git_dir);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    let file_sha = "0c5255b6f47649305fcb68edccb285510aec71a7";
+
+    let emit = |sources: Vec<Box<dyn super::RevisionSource>>| {
+        let builder = super::Builder::new().manifest_dir(git_dir).sources(sources);
+        let (res, out) = emit_with_env(builder, &[("CRATE_GIT_REVISION", file_sha)]);
+        res.map(|revision| (revision, out))
+    };
+
+    // Sources are tried in order until one has a revision, and the rerun
+    // directives of all sources tried are emitted.
+    let (revision, out) = emit(vec![
+        Box::new(FileSource("REVISION")),
+        Box::new(super::sources::Git),
+    ])
+    .unwrap();
+    assert_eq!(revision.sha, head);
+    assert!(out.starts_with("cargo:rerun-if-changed=REVISION\ncargo:rerun-if-changed=.git/index\n"));
+
+    fs::write(git_dir.join("REVISION"), file_sha).unwrap();
+    let (revision, out) = emit(vec![
+        Box::new(FileSource("REVISION")),
+        Box::new(super::sources::Git),
+    ])
+    .unwrap();
+    assert_eq!(revision.sha, file_sha);
+    assert_eq!(
+        out,
+        format!("cargo:rerun-if-changed=REVISION\ncargo:rustc-env=GIT_REVISION={file_sha}\n")
+    );
+
+    // Only the sources configured are used.
+    let (revision, _) = emit(vec![
+        Box::new(super::sources::Git),
+        Box::new(super::sources::EnvOverride),
+    ])
+    .unwrap();
+    assert_eq!(revision.sha, head);
+
+    // Errors stop the chain, and nothing is emitted.
+    fs::write(git_dir.join("REVISION"), "").unwrap();
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(git_dir)
+        .sources(vec![
+            Box::new(FileSource("REVISION")),
+            Box::new(super::sources::Git),
+        ])
+        .try_emit_to_with_env(&mut out, &no_env);
+    assert!(matches!(res, Err(super::Error::Custom(e)) if e.to_string() == "empty"));
+    assert!(out.is_empty());
+
+    let res = emit(vec![Box::new(FileSource("MISSING"))]);
+    assert!(matches!(res, Err(super::Error::NotARepository)));
+}
+
+#[test]
+fn test_sha256() {
+    use super::ObjectFormat;
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let git_dir = tempdir.path();
+
+    git(git_dir, &["init", "--object-format=sha256"]);
+    git(git_dir, &["config", "user.email", "whatever@example.com"]);
+    git(git_dir, &["config", "user.name", "Whatever"]);
+    git(git_dir, &["commit", "--allow-empty", "-m", "initial"]);
+    let head = git(git_dir, &["rev-parse", "HEAD"]);
+    assert_eq!(head.len(), 64);
+
+    for backend in backends() {
+        let mut out = Vec::new();
+        let res = super::Builder::new()
+            .manifest_dir(git_dir)
+            .backend(backend)
+            .emit_object_format(true)
+            .try_emit_to_with_env(&mut out, &no_env);
+        let revision = match backend {
+            super::Backend::Command | super::Backend::Files => res.unwrap(),
+            // The libraries cannot open SHA-256 repositories.
+            _ => {
+                assert!(
+                    matches!(
+                        res,
+                        Err(super::Error::UnsupportedObjectFormat {
+                            backend: b,
+                            object_format: ObjectFormat::Sha256,
+                        }) if b == backend
+                    ),
+                    "{backend:?}: {res:?}"
+                );
+                assert!(out.is_empty());
+                continue;
+            }
+        };
+        assert_eq!(revision.sha, head, "{backend:?}");
+        assert_eq!(revision.object_format, Some(ObjectFormat::Sha256));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("cargo:rustc-env=GIT_REVISION={head}\n")));
+        assert!(out.contains("cargo:rustc-env=GIT_OBJECT_FORMAT=sha256\n"));
+
+        let sha = super::GitRevision::parse(&revision.sha).unwrap();
+        assert_eq!(sha.object_format(), ObjectFormat::Sha256);
+    }
+
+    // A hash of the wrong length for the object format is rejected.
+    let sha1 = "0c5255b6f47649305fcb68edccb285510aec71a7";
+    fs::write(git_dir.join(".git/HEAD"), format!("{sha1}\n")).unwrap();
+    let mut out = Vec::new();
+    let res = super::Builder::new()
+        .manifest_dir(git_dir)
+        .backend(super::Backend::Files)
+        .try_emit_to_with_env(&mut out, &no_env);
+    assert!(matches!(
+        res,
+        Err(super::Error::InvalidSha { ref sha, object_format: ObjectFormat::Sha256 }) if sha == sha1
+    ));
+    assert!(out.is_empty());
+
+    // SHA-1 repositories, and revisions not read from a repository.
+    let tempdir = tempfile::tempdir().unwrap();
+    init_git_repo(tempdir.path());
+    let revision = try_init_with_backend(tempdir.path(), super::Backend::Command).unwrap();
+    assert_eq!(revision.object_format, Some(ObjectFormat::Sha1));
+
+    let sha256 = "5".repeat(64);
+    let crate_dir = tempfile::tempdir().unwrap();
+    let builder = super::Builder::new()
+        .manifest_dir(crate_dir.path())
+        .emit_object_format(true);
+    let (res, out) = emit_with_env(builder, &[("CRATE_GIT_REVISION", &sha256)]);
+    assert_eq!(res.unwrap().object_format, Some(ObjectFormat::Sha256));
+    assert!(out.contains("cargo:rustc-env=GIT_OBJECT_FORMAT=sha256\n"));
+
+    fs::write(tempdir.path().join("REVISION"), "v1.0.0").unwrap();
+    let mut out = Vec::new();
+    let revision = super::Builder::new()
+        .manifest_dir(tempdir.path())
+        .sources(vec![Box::new(FileSource("REVISION"))])
+        .emit_object_format(true)
+        .try_emit_to_with_env(&mut out, &no_env)
+        .unwrap();
+    assert_eq!(revision.object_format, None);
+    assert!(!String::from_utf8(out)
+        .unwrap()
+        .contains("GIT_OBJECT_FORMAT"));
+}
@@ -0,0 +1,48 @@
+/// Whether the repository URL of a crate, as in the `repository` field of its
+/// manifest, refers to the same repository as any of the remote URLs of the
+/// repository containing it.
+///
+/// URLs are compared by the name of the repository, the last component of the
+/// remote URL, which the crate's URL must contain as a component. Forks and
+/// mirrors, which are cloned with different hosts or owners, still match, as do
+/// URLs of crates in subdirectories, such as
+/// `https://github.com/owner/repo/tree/main/crates/example`.
+pub(crate) fn matches_remotes(repository: &str, remotes: &[String]) -> bool {
+    let repository = components(repository).collect::<Vec<_>>();
+    remotes
+        .iter()
+        .any(|remote| name(remote).map_or(false, |name| repository.contains(&name)))
+}
+
+/// The name of the repository of a remote URL, its last component. Cargo
+/// checks out git dependencies from its clone in
+/// `$CARGO_HOME/git/db/<name>-<hash>`, which is the remote of the checkout,
+/// so the hash is removed.
+fn name(remote: &str) -> Option<String> {
+    let components = components(remote).collect::<Vec<_>>();
+    let (name, parents) = components.split_last()?;
+    if let [.., git, db] = parents {
+        if git == "git" && db == "db" {
+            if let Some((name, hash)) = name.rsplit_once('-') {
+                if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Some(name.to_string());
+                }
+            }
+        }
+    }
+    Some(name.clone())
+}
+
+/// The components of the path of a URL, in lowercase without a `.git`
+/// suffix. Scp-like URLs, `git@host:owner/repo.git`, and local paths are
+/// supported.
+fn components(url: &str) -> impl Iterator<Item = String> + '_ {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, path)| path),
+        None => url.split_once(':').map_or(url, |(_, path)| path),
+    };
+    path.split(['/', '\\'])
+        .map(|component| component.strip_suffix(".git").unwrap_or(component))
+        .filter(|component| !component.is_empty())
+        .map(str::to_lowercase)
+}
@@ -0,0 +1,52 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+/// The path of the current directory relative to the root of the working
+/// directory, which is empty at the root, or `None` if the current directory
+/// is outside the working directory.
+pub(crate) fn prefix(workdir: &Path, current_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let workdir = fs::canonicalize(workdir)?;
+    let current_dir = fs::canonicalize(current_dir)?;
+    Ok(current_dir
+        .strip_prefix(&workdir)
+        .ok()
+        .map(Path::to_path_buf))
+}
+
+/// Make paths relative to the current directory relative to the root of the
+/// working directory, as the libraries match paths from the root.
+///
+/// Paths outside the working directory are skipped. Returns `None` if a path
+/// is the root itself, in which case the whole working directory is covered.
+pub(crate) fn relative_paths(
+    workdir: &Path,
+    current_dir: &Path,
+    paths: &[PathBuf],
+) -> io::Result<Option<Vec<PathBuf>>> {
+    let workdir = fs::canonicalize(workdir)?;
+    let current_dir = fs::canonicalize(current_dir)?;
+    let mut relative = Vec::new();
+    for path in paths {
+        // Normalized without touching the file system, as the path may have
+        // been deleted.
+        let mut normalized = PathBuf::new();
+        for component in current_dir.join(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        if let Ok(path) = normalized.strip_prefix(&workdir) {
+            if path.as_os_str().is_empty() {
+                return Ok(None);
+            }
+            relative.push(path.to_path_buf());
+        }
+    }
+    Ok(Some(relative))
+}